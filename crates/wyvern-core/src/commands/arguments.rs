@@ -0,0 +1,398 @@
+use wyvern_values::{DVec3, IVec3, Id};
+
+use crate::{
+    actors::ActorResult,
+    player::{Player, PlayerComponents},
+    server::Server,
+};
+
+use super::CommandSender;
+
+/// How much of the input a string argument consumes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StringKind {
+    /// A single word.
+    Word,
+    /// A single word, or a phrase in double quotes.
+    Quotable,
+    /// Everything until the end of the command.
+    Greedy,
+}
+
+/// The type of an argument node, which decides how it is parsed and how the client
+/// validates and completes it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ArgumentType {
+    Int {
+        min: Option<i32>,
+        max: Option<i32>,
+    },
+    Float {
+        min: Option<f32>,
+        max: Option<f32>,
+    },
+    String(StringKind),
+    /// The name of an online player, or a selector matching a single player.
+    Player,
+    /// An entity selector like `@a`, or a player name.
+    Entity,
+    /// A block position, where each coordinate may be relative to the sender with `~`.
+    BlockPos,
+    Id,
+}
+
+impl ArgumentType {
+    pub fn int() -> ArgumentType {
+        ArgumentType::Int {
+            min: None,
+            max: None,
+        }
+    }
+
+    pub fn float() -> ArgumentType {
+        ArgumentType::Float {
+            min: None,
+            max: None,
+        }
+    }
+
+    pub(crate) fn parse(
+        &self,
+        reader: &mut StringReader,
+        server: &Server,
+        sender: &CommandSender,
+    ) -> Result<ArgumentValue, String> {
+        match self {
+            ArgumentType::Int { min, max } => {
+                let word = reader.read_word();
+                let value = word
+                    .parse::<i32>()
+                    .map_err(|_| format!("Invalid integer '{}'", word))?;
+                if min.is_some_and(|min| value < min) || max.is_some_and(|max| value > max) {
+                    return Err(format!("Integer {} is out of range", value));
+                }
+                Ok(ArgumentValue::Int(value))
+            }
+            ArgumentType::Float { min, max } => {
+                let word = reader.read_word();
+                let value = word
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|value| value.is_finite())
+                    .ok_or_else(|| format!("Invalid float '{}'", word))?;
+                if min.is_some_and(|min| value < min) || max.is_some_and(|max| value > max) {
+                    return Err(format!("Float {} is out of range", value));
+                }
+                Ok(ArgumentValue::Float(value))
+            }
+            ArgumentType::String(kind) => {
+                let value = match kind {
+                    StringKind::Word => reader.read_word().to_string(),
+                    StringKind::Quotable => reader.read_quotable()?,
+                    StringKind::Greedy => reader.read_rest().to_string(),
+                };
+                if value.is_empty() {
+                    return Err("Expected a string".to_string());
+                }
+                Ok(ArgumentValue::String(value))
+            }
+            ArgumentType::Player => {
+                let selector = EntitySelector::parse(reader.read_word())?;
+                let players = selector
+                    .players(server, sender)
+                    .map_err(|_| "Failed to look up players".to_string())?;
+                match players.as_slice() {
+                    [player] => Ok(ArgumentValue::Player(player.clone())),
+                    [] => Err("No player was found".to_string()),
+                    _ => Err(
+                        "Only one player is allowed, but the selector allows more than one"
+                            .to_string(),
+                    ),
+                }
+            }
+            ArgumentType::Entity => Ok(ArgumentValue::Entity(EntitySelector::parse(
+                reader.read_word(),
+            )?)),
+            ArgumentType::BlockPos => {
+                let origin = sender_position(sender).floor();
+                let x = parse_coordinate(reader.read_word(), origin.x)?;
+                reader.expect(' ')?;
+                let y = parse_coordinate(reader.read_word(), origin.y)?;
+                reader.expect(' ')?;
+                let z = parse_coordinate(reader.read_word(), origin.z)?;
+                Ok(ArgumentValue::BlockPos(IVec3::new(x, y, z)))
+            }
+            ArgumentType::Id => Ok(ArgumentValue::Id(parse_id(reader.read_word())?)),
+        }
+    }
+
+    /// Suggestions the server offers for this type when the node has no custom provider.
+    pub(crate) fn default_suggestions(&self, server: &Server) -> Vec<String> {
+        let names = || {
+            server
+                .players()
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|player| player.username().ok())
+                .collect::<Vec<_>>()
+        };
+        match self {
+            ArgumentType::Player => names(),
+            ArgumentType::Entity => ["@a", "@e", "@p", "@r", "@s"]
+                .into_iter()
+                .map(str::to_string)
+                .chain(names())
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// The parsed value of an argument.
+#[derive(Debug, Clone)]
+pub enum ArgumentValue {
+    Int(i32),
+    Float(f32),
+    String(String),
+    Player(Player),
+    Entity(EntitySelector),
+    BlockPos(IVec3),
+    Id(Id),
+}
+
+/// Types that can be read from a parsed argument with `CommandContext::get`.
+pub trait FromArgument: Sized {
+    fn from_argument(value: &ArgumentValue) -> Option<Self>;
+}
+
+macro_rules! from_argument {
+    ($($t:ty => $variant:ident)*) => {
+        $(impl FromArgument for $t {
+            fn from_argument(value: &ArgumentValue) -> Option<Self> {
+                match value {
+                    ArgumentValue::$variant(value) => Some(value.clone()),
+                    _ => None,
+                }
+            }
+        })*
+    };
+}
+
+from_argument! {
+    i32 => Int
+    f32 => Float
+    String => String
+    Player => Player
+    EntitySelector => Entity
+    IVec3 => BlockPos
+    Id => Id
+}
+
+/// Selects entities by a target selector or a player name. Selector arguments in square
+/// brackets are not supported.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EntitySelector {
+    /// `@a`
+    AllPlayers,
+    /// `@e`
+    AllEntities,
+    /// `@p`
+    NearestPlayer,
+    /// `@r`
+    RandomPlayer,
+    /// `@s`
+    Sender,
+    Name(String),
+}
+
+impl EntitySelector {
+    pub(crate) fn parse(word: &str) -> Result<EntitySelector, String> {
+        Ok(match word {
+            "@a" => EntitySelector::AllPlayers,
+            "@e" => EntitySelector::AllEntities,
+            "@p" => EntitySelector::NearestPlayer,
+            "@r" => EntitySelector::RandomPlayer,
+            "@s" => EntitySelector::Sender,
+            _ if word.starts_with('@') => return Err(format!("Unknown selector '{}'", word)),
+            "" => return Err("Expected a player name or selector".to_string()),
+            _ => EntitySelector::Name(word.to_string()),
+        })
+    }
+
+    /// Resolves the selector to the players it matches. `@e` matches every player, since
+    /// other entities are not selectable yet.
+    pub fn players(&self, server: &Server, sender: &CommandSender) -> ActorResult<Vec<Player>> {
+        let players = server.players()?;
+        Ok(match self {
+            EntitySelector::AllPlayers | EntitySelector::AllEntities => players,
+            EntitySelector::Sender => sender.player().cloned().into_iter().collect(),
+            EntitySelector::RandomPlayer => {
+                if players.is_empty() {
+                    Vec::new()
+                } else {
+                    vec![players[rand::random::<u32>() as usize % players.len()].clone()]
+                }
+            }
+            EntitySelector::NearestPlayer => {
+                let origin = sender_position(sender);
+                players
+                    .into_iter()
+                    .filter_map(|player| {
+                        let position = player.get(PlayerComponents::POSITION).ok()?;
+                        Some((position.distance_squared(origin), player))
+                    })
+                    .min_by(|(a, _), (b, _)| a.total_cmp(b))
+                    .map(|(_, player)| player)
+                    .into_iter()
+                    .collect()
+            }
+            EntitySelector::Name(name) => players
+                .into_iter()
+                .filter(|player| player.username().is_ok_and(|username| &username == name))
+                .collect(),
+        })
+    }
+}
+
+/// The position commands are relative to. The console is at the world's origin.
+fn sender_position(sender: &CommandSender) -> DVec3 {
+    sender
+        .player()
+        .and_then(|player| player.get(PlayerComponents::POSITION).ok())
+        .unwrap_or(DVec3::ZERO)
+}
+
+fn parse_coordinate(word: &str, origin: f64) -> Result<i32, String> {
+    let invalid = || format!("Invalid coordinate '{}'", word);
+    // Block coordinates round down, so -0.5 is in block -1.
+    let origin = origin.floor() as i32;
+    match word.strip_prefix('~') {
+        Some("") => Ok(origin),
+        Some(offset) => offset
+            .parse::<i32>()
+            .ok()
+            .and_then(|offset| origin.checked_add(offset))
+            .ok_or_else(invalid),
+        None => word.parse::<i32>().map_err(|_| invalid()),
+    }
+}
+
+fn parse_id(word: &str) -> Result<Id, String> {
+    let (namespace, path) = word.split_once(':').unwrap_or(("minecraft", word));
+    let valid_namespace = namespace
+        .chars()
+        .all(|c| matches!(c, 'a'..='z' | '0'..='9' | '_' | '-' | '.'));
+    let valid_path = path
+        .chars()
+        .all(|c| matches!(c, 'a'..='z' | '0'..='9' | '_' | '-' | '.' | '/'));
+    if namespace.is_empty() || path.is_empty() || !valid_namespace || !valid_path {
+        return Err(format!("Invalid id '{}'", word));
+    }
+    Ok(Id::new(namespace, path))
+}
+
+/// Reads a command one token at a time, keeping track of the position for suggestions.
+pub(crate) struct StringReader<'a> {
+    input: &'a str,
+    cursor: usize,
+}
+
+impl<'a> StringReader<'a> {
+    pub(crate) fn new(input: &'a str) -> StringReader<'a> {
+        StringReader { input, cursor: 0 }
+    }
+
+    pub(crate) fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub(crate) fn set_cursor(&mut self, cursor: usize) {
+        self.cursor = cursor;
+    }
+
+    pub(crate) fn peek(&self) -> Option<char> {
+        self.input[self.cursor..].chars().next()
+    }
+
+    pub(crate) fn expect(&mut self, c: char) -> Result<(), String> {
+        if self.peek() != Some(c) {
+            return Err(format!("Expected '{}'", c));
+        }
+        self.cursor += c.len_utf8();
+        Ok(())
+    }
+
+    pub(crate) fn read_word(&mut self) -> &'a str {
+        let rest = &self.input[self.cursor..];
+        let word = rest.split(' ').next().unwrap_or_default();
+        self.cursor += word.len();
+        word
+    }
+
+    pub(crate) fn read_rest(&mut self) -> &'a str {
+        let rest = &self.input[self.cursor..];
+        self.cursor = self.input.len();
+        rest
+    }
+
+    pub(crate) fn read_quotable(&mut self) -> Result<String, String> {
+        if self.peek() != Some('"') {
+            return Ok(self.read_word().to_string());
+        }
+        self.cursor += 1;
+
+        let mut value = String::new();
+        let mut escaped = false;
+        for (i, c) in self.input[self.cursor..].char_indices() {
+            match c {
+                _ if escaped => {
+                    value.push(c);
+                    escaped = false;
+                }
+                '\\' => escaped = true,
+                '"' => {
+                    self.cursor += i + 1;
+                    return Ok(value);
+                }
+                _ => value.push(c),
+            }
+        }
+        Err("Unclosed quoted string".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EntitySelector, StringReader, parse_coordinate, parse_id};
+
+    #[test]
+    fn quoted_strings() {
+        let mut reader = StringReader::new(r#""hello \"world\"" next"#);
+        assert_eq!(reader.read_quotable().unwrap(), r#"hello "world""#);
+        assert_eq!(reader.peek(), Some(' '));
+
+        let mut reader = StringReader::new("\"unclosed");
+        assert!(reader.read_quotable().is_err());
+    }
+
+    #[test]
+    fn coordinates() {
+        assert_eq!(parse_coordinate("~", 10.0), Ok(10));
+        assert_eq!(parse_coordinate("~-3", 10.0), Ok(7));
+        assert_eq!(parse_coordinate("5", 10.0), Ok(5));
+        assert!(parse_coordinate("~x", 10.0).is_err());
+        assert_eq!(parse_coordinate("~", -0.5), Ok(-1));
+        assert_eq!(parse_coordinate("~1", -0.5), Ok(0));
+        assert!(parse_coordinate("~2147483647", 10.0).is_err());
+        assert!(parse_coordinate("~-2147483648", -1.0).is_err());
+    }
+
+    #[test]
+    fn ids_and_selectors() {
+        assert_eq!(parse_id("stone").unwrap().to_string(), "minecraft:stone");
+        assert_eq!(parse_id("wyvern:a/b").unwrap().to_string(), "wyvern:a/b");
+        assert!(parse_id("Bad:Id").is_err());
+        assert_eq!(EntitySelector::parse("@a"), Ok(EntitySelector::AllPlayers));
+        assert!(EntitySelector::parse("@x").is_err());
+    }
+}
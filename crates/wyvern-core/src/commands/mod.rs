@@ -0,0 +1,8 @@
+mod arguments;
+pub use arguments::*;
+mod node;
+pub use node::*;
+mod registry;
+pub use registry::*;
+mod sender;
+pub use sender::*;
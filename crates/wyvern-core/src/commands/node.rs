@@ -0,0 +1,177 @@
+use std::{collections::HashMap, fmt::Debug, sync::Arc};
+
+use crate::{
+    actors::{ActorError, ActorResult},
+    events::BoxedFuture,
+    server::Server,
+};
+
+use super::{ArgumentType, ArgumentValue, CommandSender, FromArgument, StringReader};
+
+pub type CommandHandler = Arc<dyn Fn(Arc<CommandContext>) -> BoxedFuture + Send + Sync>;
+/// Produces suggestions for an argument from the sender and what they have typed so far.
+pub type SuggestionProvider = Arc<dyn Fn(&CommandSender, &str) -> Vec<String> + Send + Sync>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum NodeKind {
+    Literal(String),
+    Argument { name: String, ty: ArgumentType },
+}
+
+/// A node of a command tree. Commands are registered as literal nodes, with more literal
+/// and argument nodes below them. Any node with a handler may end a command.
+#[derive(Clone)]
+pub struct CommandNode {
+    pub(crate) kind: NodeKind,
+    pub(crate) children: Vec<CommandNode>,
+    pub(crate) handler: Option<CommandHandler>,
+    pub(crate) suggestions: Option<SuggestionProvider>,
+    pub(crate) permission: Option<String>,
+}
+
+impl Debug for CommandNode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CommandNode")
+            .field("kind", &self.kind)
+            .field("children", &self.children)
+            .field("executable", &self.handler.is_some())
+            .field("permission", &self.permission)
+            .finish()
+    }
+}
+
+impl CommandNode {
+    pub fn literal(name: impl Into<String>) -> CommandNode {
+        CommandNode::new(NodeKind::Literal(name.into()))
+    }
+
+    pub fn argument(name: impl Into<String>, ty: ArgumentType) -> CommandNode {
+        CommandNode::new(NodeKind::Argument {
+            name: name.into(),
+            ty,
+        })
+    }
+
+    fn new(kind: NodeKind) -> CommandNode {
+        CommandNode {
+            kind,
+            children: Vec::new(),
+            handler: None,
+            suggestions: None,
+            permission: None,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match &self.kind {
+            NodeKind::Literal(name) | NodeKind::Argument { name, .. } => name,
+        }
+    }
+
+    pub fn then(mut self, child: CommandNode) -> Self {
+        self.children.push(child);
+        self
+    }
+
+    /// Sets the handler that runs when a command ends at this node.
+    pub fn executes<
+        F: 'static + Future<Output = ActorResult<()>> + Send + Sync,
+        N: 'static + Fn(Arc<CommandContext>) -> F + Send + Sync,
+    >(
+        mut self,
+        f: N,
+    ) -> Self {
+        self.handler = Some(Arc::new(move |context| -> BoxedFuture {
+            Box::pin(f(context))
+        }));
+        self
+    }
+
+    /// Replaces the suggestions of an argument node. The client asks the server for
+    /// suggestions whenever the player types this argument.
+    pub fn suggests(
+        mut self,
+        f: impl Fn(&CommandSender, &str) -> Vec<String> + Send + Sync + 'static,
+    ) -> Self {
+        self.suggestions = Some(Arc::new(f));
+        self
+    }
+
+    /// Requires a permission to use this node and everything below it. Players without
+    /// the permission do not see the node on their client.
+    pub fn requires(mut self, permission: impl Into<String>) -> Self {
+        self.permission = Some(permission.into());
+        self
+    }
+
+    /// Whether the sender may use this node. Senders whose permissions can not be
+    /// looked up may not.
+    pub(crate) fn can_use(&self, sender: &CommandSender) -> bool {
+        match &self.permission {
+            Some(permission) => sender.has_permission(permission).unwrap_or(false),
+            None => true,
+        }
+    }
+
+    /// Parses this node from the reader, returning the argument's name and value.
+    pub(crate) fn parse(
+        &self,
+        reader: &mut StringReader,
+        server: &Server,
+        sender: &CommandSender,
+    ) -> Result<Option<(String, ArgumentValue)>, String> {
+        match &self.kind {
+            NodeKind::Literal(literal) => {
+                let word = reader.read_word();
+                if word != literal.as_str() {
+                    return Err(format!("Unknown command argument '{}'", word));
+                }
+                Ok(None)
+            }
+            NodeKind::Argument { name, ty } => {
+                Ok(Some((name.clone(), ty.parse(reader, server, sender)?)))
+            }
+        }
+    }
+
+    pub(crate) fn suggest(
+        &self,
+        server: &Server,
+        sender: &CommandSender,
+        partial: &str,
+    ) -> Vec<String> {
+        if let Some(provider) = &self.suggestions {
+            return provider(sender, partial);
+        }
+        match &self.kind {
+            NodeKind::Literal(literal) => vec![literal.clone()],
+            NodeKind::Argument { ty, .. } => ty.default_suggestions(server),
+        }
+    }
+}
+
+/// Everything a command handler knows about the command it is running.
+#[derive(Debug, Clone)]
+pub struct CommandContext {
+    pub server: Server,
+    pub sender: CommandSender,
+    /// The full command, without the leading slash.
+    pub input: String,
+    pub(crate) arguments: HashMap<String, ArgumentValue>,
+}
+
+impl CommandContext {
+    /// Returns the value of an argument. Fails if the command has no argument with this name,
+    /// or if it has a different type.
+    pub fn get<T: FromArgument>(&self, name: &str) -> ActorResult<T> {
+        self.arguments
+            .get(name)
+            .and_then(T::from_argument)
+            .ok_or(ActorError::BadRequest)
+    }
+
+    /// Returns the value of an argument, or `None` if the command did not reach it.
+    pub fn get_optional<T: FromArgument>(&self, name: &str) -> Option<T> {
+        self.arguments.get(name).and_then(T::from_argument)
+    }
+}
@@ -0,0 +1,366 @@
+use std::{collections::HashMap, sync::Arc};
+
+use voxidian_protocol::{
+    packet::s2c::play::{
+        CommandArgumentParser, CommandNode as PtcCommandNode, CommandNodeData, CommandSuggestion,
+        CommandSuggestionsS2CPlayPacket, CommandsS2CPlayPacket, StringArgumentKind,
+    },
+    value::{Identifier, VarInt},
+};
+use wyvern_datatypes::text::{Text, TextColor};
+
+use crate::{actors::ActorResult, server::Server};
+
+use super::{
+    ArgumentType, ArgumentValue, CommandContext, CommandHandler, CommandNode, CommandSender,
+    NodeKind, StringKind, StringReader,
+};
+
+/// Every command registered on the server.
+#[derive(Debug, Clone, Default)]
+pub struct CommandRegistry {
+    pub(crate) commands: Vec<CommandNode>,
+}
+
+impl CommandRegistry {
+    pub(crate) fn register(&mut self, command: CommandNode) {
+        if !matches!(command.kind, NodeKind::Literal(_)) {
+            log::error!(
+                "Commands must start with a literal, {:?} was not registered",
+                command.name()
+            );
+            return;
+        }
+        self.commands.push(command);
+    }
+
+    /// Whether the first word of the input is a registered command.
+    pub(crate) fn contains(&self, input: &str) -> bool {
+        let name = input.split(' ').next().unwrap_or_default();
+        self.commands.iter().any(|command| command.name() == name)
+    }
+
+    /// Parses a command and runs its handler. If the command is invalid, the sender is told why.
+    pub(crate) async fn dispatch(
+        self: Arc<Self>,
+        server: Server,
+        sender: CommandSender,
+        input: String,
+    ) -> ActorResult<()> {
+        let mut arguments = HashMap::new();
+        let handler = {
+            let mut reader = StringReader::new(&input);
+            parse_nodes(
+                &self.commands,
+                &mut reader,
+                &server,
+                &sender,
+                &mut arguments,
+            )
+        };
+        match handler {
+            Ok(handler) => {
+                handler(Arc::new(CommandContext {
+                    server,
+                    sender,
+                    input,
+                    arguments,
+                }))
+                .await
+            }
+            Err(error) => {
+                sender.send_message(Text::literal(error).with_color(TextColor::new(255, 85, 85)))
+            }
+        }
+    }
+
+    /// Finds suggestions for the last word of an unfinished command. Returns where the
+    /// suggested text starts in the input, along with the suggestions.
+    pub(crate) fn suggest(
+        &self,
+        server: &Server,
+        sender: &CommandSender,
+        input: &str,
+    ) -> (usize, Vec<String>) {
+        let start = input.rfind(' ').map(|i| i + 1).unwrap_or(0);
+        let mut reader = StringReader::new(input);
+        let mut nodes = self.commands.as_slice();
+
+        'walk: while reader.cursor() < start {
+            let cursor = reader.cursor();
+            for node in nodes.iter().filter(|node| node.can_use(sender)) {
+                reader.set_cursor(cursor);
+                if node.parse(&mut reader, server, sender).is_ok()
+                    && reader.cursor() < start
+                    && reader.peek() == Some(' ')
+                {
+                    let _ = reader.expect(' ');
+                    nodes = &node.children;
+                    continue 'walk;
+                }
+            }
+            return (start, Vec::new());
+        }
+        if reader.cursor() != start {
+            return (start, Vec::new());
+        }
+
+        let partial = &input[start..];
+        let mut suggestions = nodes
+            .iter()
+            .filter(|node| node.can_use(sender))
+            .flat_map(|node| node.suggest(server, sender, partial))
+            .filter(|suggestion| {
+                suggestion
+                    .to_lowercase()
+                    .starts_with(&partial.to_lowercase())
+            })
+            .collect::<Vec<_>>();
+        suggestions.sort();
+        suggestions.dedup();
+        (start, suggestions)
+    }
+
+    /// Builds the `Commands` packet, which tells the client every command and how to parse
+    /// its arguments. Nodes requiring a permission `can_use` rejects are left out.
+    pub(crate) fn command_packet(&self, can_use: &dyn Fn(&str) -> bool) -> CommandsS2CPlayPacket {
+        let mut nodes = Vec::new();
+        let children = self
+            .commands
+            .iter()
+            .filter(|command| visible(command, can_use))
+            .map(|command| encode_node(command, &mut nodes, can_use))
+            .collect::<Vec<_>>();
+
+        // The root node has no name and no handler, only the commands below it.
+        nodes.push(PtcCommandNode {
+            children: children.into(),
+            redirect: None,
+            data: CommandNodeData::Root,
+            executable: false,
+            suggestions: None,
+        });
+        CommandsS2CPlayPacket {
+            root_index: VarInt::from(nodes.len() as i32 - 1),
+            nodes: nodes.into(),
+        }
+    }
+}
+
+/// Parses the rest of the command below the given nodes. Literals and arguments are tried
+/// in the order they were added, and the first one that parses the whole command wins.
+fn parse_nodes(
+    nodes: &[CommandNode],
+    reader: &mut StringReader,
+    server: &Server,
+    sender: &CommandSender,
+    arguments: &mut HashMap<String, ArgumentValue>,
+) -> Result<CommandHandler, String> {
+    let start = reader.cursor();
+    let mut error = None;
+    for node in nodes {
+        reader.set_cursor(start);
+        if !node.can_use(sender) {
+            // Only complain if the sender actually typed this node.
+            if node.parse(reader, server, sender).is_ok() {
+                error = Some("You do not have permission to use this command".to_string());
+            }
+            continue;
+        }
+        let value = match node.parse(reader, server, sender) {
+            Ok(value) => value,
+            Err(err) => {
+                error.get_or_insert(err);
+                continue;
+            }
+        };
+
+        let mut node_arguments = arguments.clone();
+        if let Some((name, value)) = value {
+            node_arguments.insert(name, value);
+        }
+        match reader.peek() {
+            None => match &node.handler {
+                Some(handler) => {
+                    *arguments = node_arguments;
+                    return Ok(handler.clone());
+                }
+                None => error = Some("Incomplete command".to_string()),
+            },
+            Some(' ') => {
+                let _ = reader.expect(' ');
+                match parse_nodes(&node.children, reader, server, sender, &mut node_arguments) {
+                    Ok(handler) => {
+                        *arguments = node_arguments;
+                        return Ok(handler);
+                    }
+                    // Errors further into the command are more useful than ones about
+                    // other branches.
+                    Err(err) => error = Some(err),
+                }
+            }
+            Some(_) => {
+                error.get_or_insert("Expected a space after the argument".to_string());
+            }
+        }
+    }
+    Err(error.unwrap_or_else(|| "Incorrect argument for command".to_string()))
+}
+
+fn visible(node: &CommandNode, can_use: &dyn Fn(&str) -> bool) -> bool {
+    node.permission
+        .as_deref()
+        .is_none_or(|permission| can_use(permission))
+}
+
+/// Encodes a node and everything below it, returning the node's index.
+fn encode_node(
+    node: &CommandNode,
+    nodes: &mut Vec<PtcCommandNode>,
+    can_use: &dyn Fn(&str) -> bool,
+) -> VarInt {
+    let children = node
+        .children
+        .iter()
+        .filter(|child| visible(child, can_use))
+        .map(|child| encode_node(child, nodes, can_use))
+        .collect::<Vec<_>>();
+
+    let data = match &node.kind {
+        NodeKind::Literal(name) => CommandNodeData::Literal { name: name.clone() },
+        NodeKind::Argument { name, ty } => CommandNodeData::Argument {
+            name: name.clone(),
+            parser: parser(ty),
+        },
+    };
+    nodes.push(PtcCommandNode {
+        children: children.into(),
+        redirect: None,
+        data,
+        executable: node.handler.is_some(),
+        suggestions: node
+            .suggestions
+            .as_ref()
+            .map(|_| Identifier::new("minecraft", "ask_server")),
+    });
+    VarInt::from(nodes.len() as i32 - 1)
+}
+
+/// The parser the client uses to validate an argument of this type.
+fn parser(ty: &ArgumentType) -> CommandArgumentParser {
+    match ty {
+        ArgumentType::Float { min, max } => CommandArgumentParser::Float {
+            min: *min,
+            max: *max,
+        },
+        ArgumentType::Int { min, max } => CommandArgumentParser::Integer {
+            min: *min,
+            max: *max,
+        },
+        ArgumentType::String(kind) => CommandArgumentParser::String(match kind {
+            StringKind::Word => StringArgumentKind::SingleWord,
+            StringKind::Quotable => StringArgumentKind::QuotablePhrase,
+            StringKind::Greedy => StringArgumentKind::GreedyPhrase,
+        }),
+        ArgumentType::Player => CommandArgumentParser::Entity {
+            single: true,
+            players_only: true,
+        },
+        ArgumentType::Entity => CommandArgumentParser::Entity {
+            single: false,
+            players_only: false,
+        },
+        ArgumentType::BlockPos => CommandArgumentParser::BlockPos,
+        ArgumentType::Id => CommandArgumentParser::ResourceLocation,
+    }
+}
+
+/// Builds a `CommandSuggestions` packet. `start` and `length` are the range of `text` being
+/// completed, in bytes.
+pub(crate) fn suggestions_packet(
+    id: i32,
+    text: &str,
+    start: usize,
+    length: usize,
+    matches: Vec<String>,
+) -> CommandSuggestionsS2CPlayPacket {
+    let (start, length) = utf16_range(text, start, length);
+    CommandSuggestionsS2CPlayPacket {
+        id: VarInt::from(id),
+        start: VarInt::from(start as i32),
+        length: VarInt::from(length as i32),
+        matches: matches
+            .into_iter()
+            .map(|text| CommandSuggestion {
+                text,
+                tooltip: None,
+            })
+            .collect::<Vec<_>>()
+            .into(),
+    }
+}
+
+/// Converts a byte range of `text` to UTF-16 code units, which is how the client measures
+/// text.
+fn utf16_range(text: &str, start: usize, length: usize) -> (usize, usize) {
+    (
+        text[..start].encode_utf16().count(),
+        text[start..start + length].encode_utf16().count(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use voxidian_protocol::packet::{PacketBuf, PacketEncode};
+
+    use super::{CommandRegistry, utf16_range};
+    use crate::commands::{ArgumentType, CommandNode};
+
+    #[test]
+    fn graph_layout() {
+        let mut registry = CommandRegistry::default();
+        registry.register(CommandNode::literal("give").then(
+            CommandNode::argument("count", ArgumentType::int()).executes(|_| async { Ok(()) }),
+        ));
+        registry.register(
+            CommandNode::literal("stop")
+                .requires("wyvern.stop")
+                .executes(|_| async { Ok(()) }),
+        );
+        let mut buf = PacketBuf::new();
+        registry
+            .command_packet(&|permission| permission != "wyvern.stop")
+            .encode(&mut buf)
+            .unwrap();
+        let data = buf.iter().collect::<Vec<u8>>();
+
+        let mut expected = vec![3];
+        // The argument node comes first, since children are written before their parents.
+        expected.extend_from_slice(&[0x06, 0, 5]);
+        expected.extend_from_slice(b"count");
+        expected.extend_from_slice(&[3, 0]);
+        expected.extend_from_slice(&[0x01, 1, 0, 4]);
+        expected.extend_from_slice(b"give");
+        expected.extend_from_slice(&[0x00, 1, 1]);
+        expected.push(2);
+        assert_eq!(data, expected);
+    }
+
+    #[test]
+    fn registered_commands() {
+        let mut registry = CommandRegistry::default();
+        registry.register(CommandNode::literal("spawn"));
+        registry.register(CommandNode::argument("bad", ArgumentType::int()));
+        assert!(registry.contains("spawn"));
+        assert!(registry.contains("spawn now"));
+        assert!(!registry.contains("bad"));
+        assert!(!registry.contains("spawner"));
+    }
+
+    #[test]
+    fn suggestion_ranges_count_utf16() {
+        assert_eq!(utf16_range("/give 5", 6, 1), (6, 1));
+        // 'é' is two bytes but one UTF-16 unit, and '𝄞' is four bytes but two units.
+        assert_eq!(utf16_range("/say é 𝄞", 8, 4), (7, 2));
+    }
+}
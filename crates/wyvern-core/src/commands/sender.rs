@@ -0,0 +1,95 @@
+use std::net::SocketAddr;
+
+use wyvern_datatypes::text::Text;
+use wyvern_values::cell::Token;
+
+use crate::{actors::ActorResult, player::Player};
+
+/// Whoever ran a command. Handlers that only talk back to the sender work the same for
+/// players and the console.
+#[derive(Debug, Clone)]
+pub enum CommandSender {
+    Player(Player),
+    Console(ConsoleSender),
+}
+
+impl CommandSender {
+    pub fn send_message(&self, message: Text) -> ActorResult<()> {
+        match self {
+            CommandSender::Player(player) => player.send_message(message),
+            CommandSender::Console(console) => {
+                console.send_message(message);
+                Ok(())
+            }
+        }
+    }
+
+    /// The player's username, or `Server` for the console.
+    pub fn name(&self) -> ActorResult<String> {
+        match self {
+            CommandSender::Player(player) => player.username(),
+            CommandSender::Console(_) => Ok("Server".to_string()),
+        }
+    }
+
+    pub fn player(&self) -> Option<&Player> {
+        match self {
+            CommandSender::Player(player) => Some(player),
+            CommandSender::Console(_) => None,
+        }
+    }
+
+    /// Whether the sender has a permission. The console has every permission.
+    pub fn has_permission(&self, permission: &str) -> ActorResult<bool> {
+        match self {
+            CommandSender::Player(player) => player.has_permission(permission),
+            CommandSender::Console(_) => Ok(true),
+        }
+    }
+
+    pub fn is_console(&self) -> bool {
+        matches!(self, CommandSender::Console(_))
+    }
+}
+
+/// Where a console command came from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConsoleSource {
+    /// The server's standard input.
+    Terminal,
+    /// An RCON client at the given address.
+    Rcon(SocketAddr),
+}
+
+/// The server console. Messages sent to the terminal are logged, while messages sent to
+/// RCON clients are collected and sent back as the command's response.
+#[derive(Debug, Clone)]
+pub struct ConsoleSender {
+    source: ConsoleSource,
+    output: Token<Vec<Text>>,
+}
+
+impl ConsoleSender {
+    pub(crate) fn new(source: ConsoleSource) -> ConsoleSender {
+        ConsoleSender {
+            source,
+            output: Token::new(Vec::new()),
+        }
+    }
+
+    pub fn source(&self) -> &ConsoleSource {
+        &self.source
+    }
+
+    pub fn send_message(&self, message: Text) {
+        match self.source {
+            ConsoleSource::Terminal => log::info!("{}", message.to_ansi()),
+            ConsoleSource::Rcon(_) => self.output.update(|output| output.push(message)),
+        }
+    }
+
+    /// Every message sent to an RCON client so far.
+    pub(crate) fn output(&self) -> Vec<Text> {
+        self.output.get()
+    }
+}
@@ -1,13 +1,9 @@
-use std::{
-    collections::HashMap,
-    sync::Arc,
-    time::{Duration, Instant},
-};
+use std::{collections::HashMap, sync::Arc, time::Instant};
 
 use crate::{
     blocks::BlockState,
     entities::{Entity, EntityComponents, EntityData, PlayerSkinData},
-    server::registries::RegistryKeys,
+    server::{TickScheduler, registries::RegistryKeys},
 };
 use chunk::{Chunk, ChunkSection};
 use flume::Sender;
@@ -45,7 +41,8 @@ pub struct DimensionData {
     pub(crate) dim_type: Id,
     pub(crate) chunk_generator: Box<dyn Fn(&mut Chunk, i32, i32) + Send>,
     pub(crate) chunk_max: (u32, u32),
-    pub(crate) last_update: Instant,
+    pub(crate) ticks: TickScheduler,
+    pub(crate) running: bool,
 }
 
 impl Dimension {
@@ -59,12 +56,16 @@ impl Dimension {
 
 impl DimensionData {
     pub fn event_loop(mut self) {
-        loop {
+        while self.running {
+            if let Ok(message) = self.receiver.recv_timeout(self.ticks.until_next_tick()) {
+                self.handle_message(message);
+            }
             self.handle_messages();
-            if Instant::now().duration_since(self.last_update) > Duration::from_millis(50) {
-                self.last_update = Instant::now();
+            if self.ticks.begin_tick().is_some() {
+                let started = Instant::now();
                 let _ = self.auto_apply_entity_properties();
                 let _ = self.propogate_entity_packets();
+                self.ticks.end_tick(started);
             }
         }
     }
@@ -72,6 +73,16 @@ impl DimensionData {
 
 #[crate::message(Dimension, DimensionMessage)]
 impl DimensionData {
+    #[GetTps]
+    pub fn tps(&self) -> ActorResult<f64> {
+        Ok(self.ticks.tps())
+    }
+
+    #[GetMspt]
+    pub fn mspt(&self) -> ActorResult<f64> {
+        Ok(self.ticks.mspt())
+    }
+
     #[ChunkCount]
     pub fn count_chunks(&self) -> ActorResult<usize> {
         Ok(self.chunks.capacity())
@@ -512,6 +523,12 @@ impl DimensionData {
         Ok(vec)
     }
 
+    #[Stop]
+    pub(crate) fn stop(&mut self) -> ActorResult<()> {
+        self.running = false;
+        Ok(())
+    }
+
     #[SetChunkLimits]
     #[doc = "Sets the maximum number of chunks this dimension can hold."]
     pub fn max_chunks(&mut self, x: u32, y: u32) -> ActorResult<()> {
@@ -542,7 +559,8 @@ impl DimensionData {
             dim_type,
             chunk_generator: Box::new(|_, _, _| {}),
             chunk_max: (i32::MAX as u32, i32::MAX as u32),
-            last_update: Instant::now(),
+            ticks: TickScheduler::new(),
+            running: true,
         }
     }
 
@@ -1,38 +1,58 @@
-use std::{fmt::Debug, pin::Pin, sync::Arc};
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    fmt::Debug,
+    net::IpAddr,
+    pin::Pin,
+    sync::{Arc, RwLock, atomic::AtomicU64},
+};
+
+use async_executor::Task;
+use flume::{Receiver, Sender};
 
 use crate::{
-    actors::ActorResult, blocks::BlockState, dimension::Dimension, entities::Entity,
-    item::ItemStack, player::Player, server::Server,
+    actors::ActorResult,
+    blocks::BlockState,
+    commands::CommandSender,
+    dimension::Dimension,
+    entities::Entity,
+    item::ItemStack,
+    player::{Compression, Player},
+    runtime::Runtime,
+    server::{Server, ServerStatus},
 };
 
-use wyvern_values::{DVec3, IVec2, IVec3, Id, Vec2, cell::Token};
+use wyvern_datatypes::text::Text;
+use wyvern_values::{DVec3, IVec2, IVec3, Id, Uuid, Vec2, cell::Token};
+
+mod registration;
+pub use registration::*;
 
 macro_rules! event_bus {
     ($($name:ident : $t:ty)*) => {
         #[derive(Default)]
         pub struct EventBus {
-            $(pub(crate) $name: Vec<Arc<dyn Fn(Arc<$t>) -> BoxedFuture + Sync + Send>>,)*
+            pub(crate) mode: DispatchMode,
+            /// Handlers of events declared outside of this crate, keyed by the event's type.
+            /// Each value is a `Vec<EventHandler<E>>`.
+            pub(crate) custom: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+            /// Handlers added while the server is running, keyed by the event's type.
+            pub(crate) registered: RwLock<HashMap<TypeId, Box<dyn Any + Send + Sync>>>,
+            pub(crate) next_registration: AtomicU64,
+            $(pub(crate) $name: Vec<EventHandler<$t>>,)*
         }
 
         $(impl crate::events::Event for $t {
-            fn add_handler<F: 'static + Future<Output = ActorResult<()>> + Send + Sync, N: 'static + Fn(Arc<$t>) -> F + Send + Sync>(bus: &mut EventBus, f: N) {
-                bus.$name.push(Arc::new(move |event| {
-                    let result = f(event);
-                    Box::pin(result)
-                }));
+            fn add_handler<F: 'static + Future<Output = ActorResult<()>> + Send + Sync, N: 'static + Fn(Arc<$t>) -> F + Send + Sync>(bus: &mut EventBus, priority: EventPriority, f: N) {
+                insert_handler(&mut bus.$name, priority, f);
             }
 
             fn dispatch(self, bus: std::sync::Arc<EventBus>) {
-                let tick1 = std::time::Instant::now();
-                let tick2 = std::time::Instant::now();
-                let time_time = tick2 - tick1;
-                let start = std::time::Instant::now();
-                let event = Arc::new(self);
-                for event_func in bus.$name.clone().into_iter() {
-                    $crate::runtime::Runtime::spawn_task(event_func(event.clone()));
-                }
-                let end = std::time::Instant::now();
-                log::debug!("Event {:?} took {:?} to execute", std::any::type_name::<Self>(), (end - start) - (time_time));
+                bus.spawn_handlers(bus.$name.clone(), Arc::new(self));
+            }
+
+            fn dispatch_tracked(self, bus: std::sync::Arc<EventBus>) -> PendingEvent<Self> {
+                bus.run_handlers(bus.$name.clone(), Arc::new(self))
             }
         })*
 
@@ -45,8 +65,9 @@ event_bus! {
     on_server_tick: ServerTickEvent
     on_player_move: PlayerMoveEvent
     on_chunk_load: ChunkLoadEvent
-    on_command: PlayerCommandEvent
+    on_command: CommandEvent
     on_server_start: ServerStartEvent
+    on_server_stop: ServerStopEvent
     on_place_block: PlaceBlockEvent
     start_break_block: StartBreakBlockEvent
     on_change_held_slot: ChangeHeldSlotEvent
@@ -60,6 +81,9 @@ event_bus! {
     on_left_click: PlayerLeftClickEvent
     on_load: PlayerLoadEvent
     on_respawn: PlayerRespawnEvent
+    on_status: ServerStatusEvent
+    on_pre_login: PlayerPreLoginEvent
+    on_plugin_message: PluginMessageEvent
 }
 
 impl Debug for EventBus {
@@ -68,15 +92,275 @@ impl Debug for EventBus {
     }
 }
 
-pub trait Event {
+impl EventBus {
+    fn custom_handlers<E: Event>(&self) -> Vec<EventHandler<E>> {
+        self.custom
+            .get(&TypeId::of::<E>())
+            .and_then(|handlers| handlers.downcast_ref::<Vec<EventHandler<E>>>())
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn custom_handlers_mut<E: Event>(&mut self) -> &mut Vec<EventHandler<E>> {
+        self.custom
+            .entry(TypeId::of::<E>())
+            .or_insert_with(|| Box::new(Vec::<EventHandler<E>>::new()))
+            .downcast_mut()
+            .expect("handlers are stored under the type id of their event")
+    }
+
+    fn spawn_handlers<E: Event>(&self, handlers: Vec<EventHandler<E>>, event: Arc<E>) {
+        let start = std::time::Instant::now();
+        let handlers = self.with_registered(handlers);
+        if handlers.is_empty() {
+            return;
+        }
+        match self.mode {
+            DispatchMode::Sequential => {
+                Runtime::spawn_task(run_sequential(handlers, event));
+            }
+            DispatchMode::Parallel => {
+                for handler in handlers {
+                    Runtime::spawn_task((handler.handler)(event.clone()));
+                }
+            }
+        }
+        log::debug!(
+            "Event {:?} took {:?} to execute",
+            std::any::type_name::<E>(),
+            start.elapsed()
+        );
+    }
+
+    fn run_handlers<E: Event>(
+        &self,
+        handlers: Vec<EventHandler<E>>,
+        event: Arc<E>,
+    ) -> PendingEvent<E> {
+        let handlers = self.with_registered(handlers);
+        // Dropping the sender right away leaves the event finished, without spawning a task.
+        let (finished_tx, finished) = flume::bounded(0);
+        let tasks = match self.mode {
+            _ if handlers.is_empty() => Vec::new(),
+            DispatchMode::Sequential => vec![Runtime::run_async(notify_finished(
+                finished_tx,
+                run_sequential(handlers, event.clone()),
+            ))],
+            DispatchMode::Parallel => handlers
+                .iter()
+                .map(|handler| {
+                    Runtime::run_async(notify_finished(
+                        finished_tx.clone(),
+                        (handler.handler)(event.clone()),
+                    ))
+                })
+                .collect(),
+        };
+        PendingEvent {
+            event,
+            tasks,
+            finished,
+        }
+    }
+}
+
+/// Something that happens on the server, which handlers can react to.
+///
+/// Any type can be an event by implementing this trait with its default methods, which
+/// keep its handlers in the bus by type:
+///
+/// ```ignore
+/// struct GameEndEvent {
+///     winner: Player,
+/// }
+///
+/// impl Event for GameEndEvent {}
+/// ```
+pub trait Event: Send + Sync + 'static {
     fn add_handler<
         F: 'static + Future<Output = ActorResult<()>> + Send + Sync,
         N: 'static + Fn(Arc<Self>) -> F + Send + Sync,
     >(
         bus: &mut EventBus,
+        priority: EventPriority,
         f: N,
+    ) where
+        Self: Sized,
+    {
+        insert_handler(bus.custom_handlers_mut::<Self>(), priority, f);
+    }
+
+    fn dispatch(self, bus: Arc<EventBus>)
+    where
+        Self: Sized,
+    {
+        bus.spawn_handlers(bus.custom_handlers::<Self>(), Arc::new(self));
+    }
+
+    fn dispatch_tracked(self, bus: Arc<EventBus>) -> PendingEvent<Self>
+    where
+        Self: Sized,
+    {
+        bus.run_handlers(bus.custom_handlers::<Self>(), Arc::new(self))
+    }
+}
+
+/// When a handler runs relative to the other handlers of the same event. Lower priorities
+/// run first, so higher priorities have the final say over the outcome.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum EventPriority {
+    Lowest,
+    Low,
+    #[default]
+    Normal,
+    High,
+    Highest,
+    /// Runs last, and should only observe the outcome of the event without changing it.
+    Monitor,
+}
+
+/// How the handlers of a single event are run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DispatchMode {
+    /// Handlers run one after another in priority order, so each one sees the changes made
+    /// by the ones before it.
+    #[default]
+    Sequential,
+    /// Every handler runs as its own task at the same time, in no particular order.
+    /// Priorities are ignored.
+    Parallel,
+}
+
+pub(crate) struct EventHandler<E> {
+    pub(crate) priority: EventPriority,
+    pub(crate) handler: Arc<dyn Fn(Arc<E>) -> BoxedFuture + Send + Sync>,
+}
+
+impl<E> Clone for EventHandler<E> {
+    fn clone(&self) -> Self {
+        EventHandler {
+            priority: self.priority,
+            handler: self.handler.clone(),
+        }
+    }
+}
+
+fn insert_handler<
+    E,
+    F: 'static + Future<Output = ActorResult<()>> + Send + Sync,
+    N: 'static + Fn(Arc<E>) -> F + Send + Sync,
+>(
+    handlers: &mut Vec<EventHandler<E>>,
+    priority: EventPriority,
+    f: N,
+) {
+    // Handlers with the same priority keep the order they were added in.
+    let index = handlers.partition_point(|handler| handler.priority <= priority);
+    handlers.insert(
+        index,
+        EventHandler {
+            priority,
+            handler: Arc::new(move |event| -> BoxedFuture { Box::pin(f(event)) }),
+        },
     );
-    fn dispatch(self, bus: Arc<EventBus>);
+}
+
+/// Runs handlers in order, waiting for each to finish before starting the next.
+/// A failing handler does not stop the ones after it.
+pub(crate) async fn run_sequential<E>(
+    handlers: Vec<EventHandler<E>>,
+    event: Arc<E>,
+) -> ActorResult<()> {
+    for handler in handlers {
+        if let Err(err) = (handler.handler)(event.clone()).await {
+            log::warn!(
+                "A handler for {} failed: {:?}",
+                std::any::type_name::<E>(),
+                err
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Runs a handler, then drops `finished` so whoever waits on its receiver wakes up.
+async fn notify_finished(
+    finished: Sender<()>,
+    handler: impl Future<Output = ActorResult<()>>,
+) -> ActorResult<()> {
+    let result = handler.await;
+    drop(finished);
+    result
+}
+
+/// An event whose handlers have been spawned, but may not have finished running yet.
+pub struct PendingEvent<E> {
+    pub(crate) event: Arc<E>,
+    pub(crate) tasks: Vec<Task<ActorResult<()>>>,
+    /// Disconnects once every handler has finished. Nothing is ever sent on it.
+    pub(crate) finished: Receiver<()>,
+}
+
+impl<E> PendingEvent<E> {
+    pub fn is_finished(&self) -> bool {
+        self.finished.is_disconnected()
+    }
+
+    /// A channel that disconnects once every handler has finished, so the event can be
+    /// waited on together with other channels.
+    pub(crate) fn finished(&self) -> &Receiver<()> {
+        &self.finished
+    }
+
+    pub fn event(&self) -> &Arc<E> {
+        &self.event
+    }
+
+    pub fn into_event(self) -> Arc<E> {
+        self.event
+    }
+
+    /// Blocks the current thread until every handler has finished.
+    /// This must not be called from an actor that the handlers may send messages to.
+    pub fn wait(self) -> Arc<E> {
+        futures::executor::block_on(async {
+            for task in self.tasks {
+                let _ = task.await;
+            }
+        });
+        self.event
+    }
+}
+
+/// An event whose handlers can stop the action that fired it. The connection waits for
+/// every handler to finish before acting, and reverts what the client predicted if the
+/// event was cancelled.
+pub trait CancellableEvent {
+    fn is_cancelled(&self) -> bool;
+}
+
+macro_rules! cancellable {
+    ($($t:ty)*) => {
+        $(impl $t {
+            pub fn cancel(&self) {
+                self.cancelled.set(true);
+            }
+        }
+
+        impl CancellableEvent for $t {
+            fn is_cancelled(&self) -> bool {
+                self.cancelled.get()
+            }
+        })*
+    };
+}
+
+cancellable! {
+    PlayerMoveEvent
+    BreakBlockEvent
+    PlaceBlockEvent
+    DropItemEvent
+    ChatMessageEvent
 }
 
 pub type BoxedFuture = Pin<Box<dyn Future<Output = ActorResult<()>> + Sync + Send + 'static>>;
@@ -96,6 +380,7 @@ pub struct ChunkLoadEvent {
 #[derive(Debug, Clone)]
 pub struct ServerTickEvent {
     pub server: Server,
+    pub tick: u64,
 }
 
 #[derive(Debug, Clone)]
@@ -103,11 +388,16 @@ pub struct PlayerMoveEvent {
     pub player: Player,
     pub new_position: DVec3,
     pub new_direction: Vec2,
+    pub cancelled: Token<bool>,
 }
 
+/// A command that was not registered with `ServerBuilder::command`, sent by a player, from
+/// the server's terminal or over RCON.
 #[derive(Debug, Clone)]
-pub struct PlayerCommandEvent {
-    pub player: Player,
+pub struct CommandEvent {
+    pub server: Server,
+    pub sender: CommandSender,
+    /// The command, without the leading slash.
     pub command: String,
 }
 
@@ -128,6 +418,7 @@ pub struct BreakBlockEvent {
     pub player: Player,
     pub position: IVec3,
     pub old_block: BlockState,
+    pub cancelled: Token<bool>,
 }
 
 #[derive(Debug, Clone)]
@@ -135,12 +426,14 @@ pub struct PlaceBlockEvent {
     pub player: Player,
     pub position: IVec3,
     pub block: BlockState,
+    pub cancelled: Token<bool>,
 }
 
 #[derive(Debug)]
 pub struct DropItemEvent {
     pub player: Player,
     pub item: ItemStack,
+    pub cancelled: Token<bool>,
 }
 
 #[derive(Debug, Clone)]
@@ -164,10 +457,17 @@ pub struct ServerStartEvent {
     pub server: Server,
 }
 
+#[derive(Debug, Clone)]
+pub struct ServerStopEvent {
+    pub server: Server,
+    pub reason: Text,
+}
+
 #[derive(Debug, Clone)]
 pub struct ChatMessageEvent {
     pub player: Player,
     pub message: String,
+    pub cancelled: Token<bool>,
 }
 
 #[derive(Debug, Clone)]
@@ -202,3 +502,201 @@ pub struct PlayerLoadEvent {
 pub struct PlayerRespawnEvent {
     pub player: Player,
 }
+
+#[derive(Debug, Clone)]
+pub struct PluginMessageEvent {
+    pub player: Player,
+    pub channel: Id,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ServerStatusEvent {
+    pub server: Server,
+    pub remote_addr: IpAddr,
+    pub protocol_version: i32,
+    pub status: Token<ServerStatus>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PlayerPreLoginEvent {
+    pub username: String,
+    pub uuid: Uuid,
+    pub addr: IpAddr,
+    /// Whether the player's identity was verified by the session server.
+    pub authenticated: bool,
+    /// Whether the player was transferred here from another server.
+    pub transferred: bool,
+    pub result: Token<PreLoginResult>,
+    /// The compression to use for this connection, which defaults to the server's.
+    pub compression: Token<Compression>,
+}
+
+impl PlayerPreLoginEvent {
+    pub fn allow(&self) {
+        self.result.set(PreLoginResult::Allowed);
+    }
+
+    pub fn deny(&self, reason: Text) {
+        self.result.set(PreLoginResult::Denied(reason));
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PreLoginResult {
+    Allowed,
+    Denied(Text),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        sync::Arc,
+    };
+
+    use wyvern_datatypes::text::Text;
+    use wyvern_values::{Uuid, cell::Token};
+
+    use super::{
+        Event, EventBus, EventPriority, PlayerPreLoginEvent, PreLoginResult, run_sequential,
+    };
+    use crate::player::Compression;
+
+    #[test]
+    fn sequential_handlers_see_earlier_changes() {
+        let mut bus = EventBus::default();
+        PlayerPreLoginEvent::add_handler(
+            &mut bus,
+            EventPriority::Monitor,
+            |event: Arc<PlayerPreLoginEvent>| async move {
+                assert_eq!(event.result.get(), PreLoginResult::Allowed);
+                Ok(())
+            },
+        );
+        PlayerPreLoginEvent::add_handler(
+            &mut bus,
+            EventPriority::High,
+            |event: Arc<PlayerPreLoginEvent>| async move {
+                assert!(matches!(event.result.get(), PreLoginResult::Denied(_)));
+                event.allow();
+                Ok(())
+            },
+        );
+        PlayerPreLoginEvent::add_handler(
+            &mut bus,
+            EventPriority::Lowest,
+            |event: Arc<PlayerPreLoginEvent>| async move {
+                event.deny(Text::literal("Not yet"));
+                Ok(())
+            },
+        );
+        let priorities = bus
+            .on_pre_login
+            .iter()
+            .map(|handler| handler.priority)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            priorities,
+            [
+                EventPriority::Lowest,
+                EventPriority::High,
+                EventPriority::Monitor
+            ]
+        );
+
+        let event = Arc::new(PlayerPreLoginEvent {
+            username: "test".to_string(),
+            uuid: Uuid::nil(),
+            addr: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            authenticated: false,
+            transferred: false,
+            result: Token::new(PreLoginResult::Allowed),
+            compression: Token::new(Compression::default()),
+        });
+        futures::executor::block_on(run_sequential(bus.on_pre_login.clone(), event.clone()))
+            .unwrap();
+        assert_eq!(event.result.get(), PreLoginResult::Allowed);
+    }
+
+    struct ScoreEvent {
+        score: Token<i32>,
+    }
+
+    impl Event for ScoreEvent {}
+
+    #[test]
+    fn custom_events_are_keyed_by_type() {
+        let mut bus = EventBus::default();
+        ScoreEvent::add_handler(
+            &mut bus,
+            EventPriority::High,
+            |event: Arc<ScoreEvent>| async move {
+                event.score.update(|score| *score *= 2);
+                Ok(())
+            },
+        );
+        ScoreEvent::add_handler(
+            &mut bus,
+            EventPriority::Normal,
+            |event: Arc<ScoreEvent>| async move {
+                event.score.update(|score| *score += 1);
+                Ok(())
+            },
+        );
+        assert_eq!(bus.custom_handlers::<ScoreEvent>().len(), 2);
+        assert!(bus.custom_handlers::<PlayerPreLoginEvent>().is_empty());
+
+        let event = Arc::new(ScoreEvent {
+            score: Token::new(1),
+        });
+        futures::executor::block_on(run_sequential(
+            bus.custom_handlers::<ScoreEvent>(),
+            event.clone(),
+        ))
+        .unwrap();
+        assert_eq!(event.score.get(), 4);
+    }
+
+    #[test]
+    fn registered_handlers_are_removed_on_drop() {
+        let mut bus = EventBus::default();
+        ScoreEvent::add_handler(
+            &mut bus,
+            EventPriority::Normal,
+            |_: Arc<ScoreEvent>| async { Ok(()) },
+        );
+        let bus = Arc::new(bus);
+
+        let first = bus.register(EventPriority::Lowest, |_: Arc<ScoreEvent>| async { Ok(()) });
+        let second = bus.register(EventPriority::Monitor, |_: Arc<ScoreEvent>| async {
+            Ok(())
+        });
+        let priorities = bus
+            .with_registered(bus.custom_handlers::<ScoreEvent>())
+            .iter()
+            .map(|handler| handler.priority)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            priorities,
+            [
+                EventPriority::Lowest,
+                EventPriority::Normal,
+                EventPriority::Monitor
+            ]
+        );
+
+        drop(first);
+        assert_eq!(
+            bus.with_registered(bus.custom_handlers::<ScoreEvent>())
+                .len(),
+            2
+        );
+        second.forget();
+        assert_eq!(
+            bus.with_registered(bus.custom_handlers::<ScoreEvent>())
+                .len(),
+            2
+        );
+    }
+}
@@ -0,0 +1,253 @@
+use std::{
+    any::TypeId,
+    fmt::Debug,
+    sync::{Arc, Weak, atomic::Ordering},
+};
+
+use flume::WeakSender;
+
+use crate::{actors::ActorResult, dimension::Dimension, player::Player, server::Server};
+
+use super::{
+    BreakBlockEvent, ChangeHeldSlotEvent, ChatMessageEvent, ChunkLoadEvent, DimensionCreateEvent,
+    DropItemEvent, Event, EventBus, EventHandler, EventPriority, PlaceBlockEvent,
+    PlayerAttackEntityEvent, PlayerAttackPlayerEvent, PlayerJoinEvent, PlayerLeftClickEvent,
+    PlayerLoadEvent, PlayerMoveEvent, PlayerRespawnEvent, PluginMessageEvent, RightClickEvent,
+    StartBreakBlockEvent, SwapHandsEvent,
+};
+
+type RegisteredHandlers<E> = Vec<(u64, EventHandler<E>)>;
+
+/// Keeps a handler added while the server is running registered. The handler is removed
+/// as soon as this is dropped.
+#[must_use = "the handler is removed as soon as the registration is dropped"]
+pub struct HandlerRegistration {
+    unregister: Option<Box<dyn FnOnce() + Send + Sync>>,
+}
+
+impl HandlerRegistration {
+    /// Keeps the handler registered until the server stops.
+    pub fn forget(mut self) {
+        self.unregister = None;
+    }
+}
+
+impl Debug for HandlerRegistration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HandlerRegistration")
+            .field("registered", &self.unregister.is_some())
+            .finish()
+    }
+}
+
+impl Drop for HandlerRegistration {
+    fn drop(&mut self) {
+        if let Some(unregister) = self.unregister.take() {
+            unregister();
+        }
+    }
+}
+
+impl EventBus {
+    pub(crate) fn register<
+        E: Event,
+        F: 'static + Future<Output = ActorResult<()>> + Send + Sync,
+        N: 'static + Fn(Arc<E>) -> F + Send + Sync,
+    >(
+        self: &Arc<Self>,
+        priority: EventPriority,
+        f: N,
+    ) -> HandlerRegistration {
+        let id = self.next_registration.fetch_add(1, Ordering::Relaxed);
+        let mut handlers = Vec::new();
+        super::insert_handler(&mut handlers, priority, f);
+        self.registered
+            .write()
+            .unwrap()
+            .entry(TypeId::of::<E>())
+            .or_insert_with(|| Box::new(RegisteredHandlers::<E>::new()))
+            .downcast_mut::<RegisteredHandlers<E>>()
+            .expect("handlers are stored under the type id of their event")
+            .extend(handlers.into_iter().map(|handler| (id, handler)));
+
+        let bus = Arc::downgrade(self);
+        HandlerRegistration {
+            unregister: Some(Box::new(move || unregister::<E>(&bus, id))),
+        }
+    }
+
+    /// Adds the handlers registered at runtime to the handlers the server was built with.
+    /// Registered handlers run after built-in ones of the same priority.
+    pub(crate) fn with_registered<E: Event>(
+        &self,
+        mut handlers: Vec<EventHandler<E>>,
+    ) -> Vec<EventHandler<E>> {
+        let registered = self.registered.read().unwrap();
+        let Some(registered) = registered
+            .get(&TypeId::of::<E>())
+            .and_then(|handlers| handlers.downcast_ref::<RegisteredHandlers<E>>())
+        else {
+            return handlers;
+        };
+        for (_, handler) in registered {
+            let index = handlers.partition_point(|other| other.priority <= handler.priority);
+            handlers.insert(index, handler.clone());
+        }
+        handlers
+    }
+}
+
+fn unregister<E: Event>(bus: &Weak<EventBus>, id: u64) {
+    // Nothing is left to remove once the server has stopped.
+    let Some(bus) = bus.upgrade() else {
+        return;
+    };
+    let mut registered = bus.registered.write().unwrap();
+    if let Some(handlers) = registered
+        .get_mut(&TypeId::of::<E>())
+        .and_then(|handlers| handlers.downcast_mut::<RegisteredHandlers<E>>())
+    {
+        handlers.retain(|(handler_id, _)| *handler_id != id);
+    }
+}
+
+impl Server {
+    /// Adds an event handler while the server is running, like `ServerBuilder::event`.
+    pub fn register_handler<
+        E: Event,
+        F: 'static + Future<Output = ActorResult<()>> + Send + Sync,
+        N: 'static + Fn(Arc<E>) -> F + Send + Sync,
+    >(
+        &self,
+        f: N,
+    ) -> ActorResult<HandlerRegistration> {
+        self.register_handler_with_priority(EventPriority::Normal, f)
+    }
+
+    pub fn register_handler_with_priority<
+        E: Event,
+        F: 'static + Future<Output = ActorResult<()>> + Send + Sync,
+        N: 'static + Fn(Arc<E>) -> F + Send + Sync,
+    >(
+        &self,
+        priority: EventPriority,
+        f: N,
+    ) -> ActorResult<HandlerRegistration> {
+        Ok(self.event_bus()?.register(priority, f))
+    }
+}
+
+/// Events caused by a single player, which can be listened to with `Player::register_handler`.
+pub trait PlayerEvent: Event {
+    fn player(&self) -> &Player;
+}
+
+/// Events that happen in a single dimension, which can be listened to with
+/// `Dimension::register_handler`. Player events happen in the player's current dimension.
+pub trait DimensionEvent: Event {
+    fn dimension(&self) -> ActorResult<Dimension>;
+}
+
+macro_rules! player_events {
+    ($($t:ty => $field:ident)*) => {
+        $(impl PlayerEvent for $t {
+            fn player(&self) -> &Player {
+                &self.$field
+            }
+        }
+
+        impl DimensionEvent for $t {
+            fn dimension(&self) -> ActorResult<Dimension> {
+                self.$field.dimension()
+            }
+        })*
+    };
+}
+
+player_events! {
+    PlayerJoinEvent => player
+    PlayerMoveEvent => player
+    StartBreakBlockEvent => player
+    BreakBlockEvent => player
+    PlaceBlockEvent => player
+    DropItemEvent => player
+    RightClickEvent => player
+    SwapHandsEvent => player
+    ChangeHeldSlotEvent => player
+    ChatMessageEvent => player
+    PlayerAttackEntityEvent => attacker
+    PlayerAttackPlayerEvent => attacker
+    PlayerLeftClickEvent => player
+    PlayerLoadEvent => player
+    PlayerRespawnEvent => player
+    PluginMessageEvent => player
+}
+
+impl DimensionEvent for DimensionCreateEvent {
+    fn dimension(&self) -> ActorResult<Dimension> {
+        Ok(self.dimension.clone())
+    }
+}
+
+impl DimensionEvent for ChunkLoadEvent {
+    fn dimension(&self) -> ActorResult<Dimension> {
+        Ok(self.dimension.clone())
+    }
+}
+
+fn same_actor<M>(a: &WeakSender<M>, b: &WeakSender<M>) -> bool {
+    match (a.upgrade(), b.upgrade()) {
+        (Some(a), Some(b)) => a.same_channel(&b),
+        _ => false,
+    }
+}
+
+impl Player {
+    /// Adds an event handler that only runs for events caused by this player.
+    pub fn register_handler<
+        E: PlayerEvent,
+        F: 'static + Future<Output = ActorResult<()>> + Send + Sync,
+        N: 'static + Fn(Arc<E>) -> F + Send + Sync,
+    >(
+        &self,
+        f: N,
+    ) -> ActorResult<HandlerRegistration> {
+        let player = self.clone();
+        Server::get()?.register_handler(move |event: Arc<E>| {
+            let scoped = same_actor(&event.player().sender, &player.sender).then(|| f(event));
+            async move {
+                match scoped {
+                    Some(handler) => handler.await,
+                    None => Ok(()),
+                }
+            }
+        })
+    }
+}
+
+impl Dimension {
+    /// Adds an event handler that only runs for events in this dimension.
+    pub fn register_handler<
+        E: DimensionEvent,
+        F: 'static + Future<Output = ActorResult<()>> + Send + Sync,
+        N: 'static + Fn(Arc<E>) -> F + Send + Sync,
+    >(
+        &self,
+        f: N,
+    ) -> ActorResult<HandlerRegistration> {
+        let dimension = self.clone();
+        let f = Arc::new(f);
+        Server::get()?.register_handler(move |event: Arc<E>| {
+            let dimension = dimension.clone();
+            let f = f.clone();
+            async move {
+                // Looking up a player's dimension needs the player's actor, so it can only
+                // happen once the handler runs.
+                match event.dimension() {
+                    Ok(other) if same_actor(&other.sender, &dimension.sender) => f(event).await,
+                    _ => Ok(()),
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,148 @@
+//! Entry points for the fuzz targets in `fuzz/`. Not part of the public API.
+
+use std::{
+    collections::VecDeque,
+    net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream},
+    sync::{Arc, LazyLock, Mutex},
+    time::Duration,
+};
+
+use voxidian_protocol::packet::{
+    PrefixedPacketDecode, Stage,
+    c2s::{
+        config::C2SConfigPackets, handshake::C2SHandshakePackets, login::C2SLoginPackets,
+        play::C2SPlayPackets, status::C2SStatusPackets,
+    },
+    processing::PacketProcessing,
+};
+
+use crate::{
+    events::ServerStartEvent,
+    player::{Compression, ConnectionData, next_packet},
+    server::{NetworkReactor, Server, ServerBuilder},
+};
+
+/// Reads packets the same way a connection does until the data runs out or is malformed.
+/// The first byte decides whether compression is enabled.
+fn read_packets<T: PrefixedPacketDecode>(data: &[u8]) {
+    let Some((&flags, data)) = data.split_first() else {
+        return;
+    };
+    let compression = if flags & 1 == 0 {
+        Compression::Disabled
+    } else {
+        Compression::default()
+    };
+
+    let mut processing = PacketProcessing::NONE;
+    let mut queue = data.iter().copied().collect::<VecDeque<u8>>();
+    while let Ok(Some(_)) = next_packet::<T>(&mut processing, compression, &mut queue) {}
+}
+
+pub fn read_handshake_packets(data: &[u8]) {
+    read_packets::<C2SHandshakePackets>(data);
+}
+
+pub fn read_status_packets(data: &[u8]) {
+    read_packets::<C2SStatusPackets>(data);
+}
+
+pub fn read_login_packets(data: &[u8]) {
+    read_packets::<C2SLoginPackets>(data);
+}
+
+pub fn read_config_packets(data: &[u8]) {
+    read_packets::<C2SConfigPackets>(data);
+}
+
+pub fn read_play_packets(data: &[u8]) {
+    read_packets::<C2SPlayPackets>(data);
+}
+
+/// A running server for connections to be handled by, and a listener to create their
+/// sockets with.
+struct FuzzServer {
+    server: Server,
+    reactor: Arc<NetworkReactor>,
+    sockets: TcpListener,
+}
+
+static FUZZ_SERVER: LazyLock<FuzzServer> = LazyLock::new(|| {
+    let (started_tx, started) = flume::bounded(1);
+    std::thread::spawn(move || {
+        ServerBuilder::new()
+            .bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
+            .event(move |event: Arc<ServerStartEvent>| {
+                let started_tx = started_tx.clone();
+                async move {
+                    let _ = started_tx.send(event.server.clone());
+                    Ok(())
+                }
+            })
+            .run()
+    });
+    let server = started
+        .recv_timeout(Duration::from_secs(60))
+        .expect("the fuzzing server should start");
+    let (reactor, _) = NetworkReactor::start(server.clone()).unwrap();
+    FuzzServer {
+        server,
+        reactor,
+        sockets: TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap(),
+    }
+});
+
+/// Runs packets through the stage handlers of a connection to a running server, the same
+/// way its thread would. The first byte picks the stage the connection starts in and
+/// whether compression is enabled.
+pub fn handle_packets(data: &[u8]) {
+    let Some((&flags, data)) = data.split_first() else {
+        return;
+    };
+    let stages = [
+        Stage::Handshake,
+        Stage::Status,
+        Stage::Login,
+        Stage::Config,
+        Stage::Play,
+    ];
+    let stage = stages[(flags >> 1) as usize % stages.len()];
+
+    let fuzz = &*FUZZ_SERVER;
+    let Ok(client) = TcpStream::connect(fuzz.sockets.local_addr().unwrap()) else {
+        return;
+    };
+    let Ok((stream, addr)) = fuzz.sockets.accept() else {
+        return;
+    };
+    if stream.set_nonblocking(true).is_err() {
+        return;
+    }
+
+    let (sender, receiver) = flume::unbounded();
+    let (signal, _signal) = flume::bounded(1);
+    let Ok(mut conn) = ConnectionData::new(
+        mio::net::TcpStream::from_std(stream),
+        addr.ip(),
+        sender,
+        receiver,
+        signal,
+        fuzz.server.clone(),
+        fuzz.reactor.clone(),
+        Arc::new(Mutex::new(stage)),
+    ) else {
+        return;
+    };
+    if flags & 1 != 0 {
+        conn.compression = Compression::default();
+    }
+
+    conn.received_bytes.extend(data);
+    conn.process_received_packets();
+
+    // Dropping the connection fails any calls to its actor that are still waiting.
+    conn.reactor
+        .deregister(&mut conn.stream, conn.reactor_token);
+    drop(conn);
+    drop(client);
+}
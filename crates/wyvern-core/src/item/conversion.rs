@@ -182,8 +182,12 @@ impl From<SlotData> for ItemStack {
                 _ => {}
             }
         }
+        // Clients can send any item id, so unknown ones become air instead of panicking.
         ItemStack {
-            id: ITEM_REGISTRY.lookup(&value.id).unwrap().id.clone().into(),
+            id: ITEM_REGISTRY
+                .lookup(&value.id)
+                .map(|item| item.id.clone().into())
+                .unwrap_or(Id::constant("minecraft", "air")),
             map,
         }
     }
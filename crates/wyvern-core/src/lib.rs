@@ -5,14 +5,20 @@ pub(crate) mod actors {
     pub use wyvern_actors::*;
 }
 pub mod blocks;
+pub mod commands;
 pub mod dimension;
 pub mod entities;
 pub mod events;
+#[cfg(feature = "fuzzing")]
+#[doc(hidden)]
+pub mod fuzz;
 pub mod inventory;
 pub mod item;
+pub mod permissions;
 pub mod player;
 pub mod plugin;
 pub mod runtime;
 pub mod server;
+pub(crate) mod varint;
 
 pub(crate) use wyvern_macros::*;
@@ -0,0 +1,197 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use wyvern_values::Uuid;
+
+mod storage;
+pub use storage::*;
+
+/// Every player is a member of this group, so it can hold the permissions everyone has.
+pub const DEFAULT_GROUP: &str = "default";
+/// The highest op level. Players at this level have every permission that is not
+/// explicitly denied to them.
+pub const MAX_OP_LEVEL: u8 = 4;
+
+/// The group players with at least the given op level are members of, like `op_level_2`.
+/// Players at a level are members of the groups of every level below it too.
+pub fn op_level_group(level: u8) -> String {
+    format!("op_level_{}", level)
+}
+
+/// The permissions stored for a single player.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PlayerPermissions {
+    /// The vanilla op level, from 0 to 4. Clients use it to decide which debug features
+    /// are available.
+    pub op_level: u8,
+    pub groups: Vec<String>,
+    /// Permission nodes granted (`true`) or denied (`false`) to the player. Nodes ending in
+    /// `*` match every node below them.
+    pub permissions: HashMap<String, bool>,
+}
+
+/// A named set of permissions that players can be added to.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PermissionGroup {
+    pub permissions: HashMap<String, bool>,
+    /// Groups this group inherits permissions from.
+    pub parents: Vec<String>,
+}
+
+/// Where permissions are loaded from and saved to.
+pub trait PermissionStorage: Send + Sync {
+    /// Returns the permissions of a player, or the defaults if none were stored.
+    fn player(&self, uuid: Uuid) -> PlayerPermissions;
+    fn set_player(&self, uuid: Uuid, permissions: PlayerPermissions);
+    fn group(&self, name: &str) -> Option<PermissionGroup>;
+    fn set_group(&self, name: &str, group: PermissionGroup);
+}
+
+/// Checks whether a player has a permission. The player's own nodes take priority over
+/// their groups, then their op level groups from the highest level down, and groups take
+/// priority over the groups they inherit from.
+pub fn check_permission(storage: &dyn PermissionStorage, uuid: Uuid, permission: &str) -> bool {
+    let player = storage.player(uuid);
+    if let Some(granted) = lookup(&player.permissions, permission) {
+        return granted;
+    }
+
+    let mut visited = HashSet::new();
+    let mut queue = player
+        .groups
+        .iter()
+        .cloned()
+        .chain(
+            (1..=player.op_level.min(MAX_OP_LEVEL))
+                .rev()
+                .map(op_level_group),
+        )
+        .chain([DEFAULT_GROUP.to_string()])
+        .collect::<VecDeque<_>>();
+    while let Some(name) = queue.pop_front() {
+        if !visited.insert(name.clone()) {
+            continue;
+        }
+        let Some(group) = storage.group(&name) else {
+            continue;
+        };
+        if let Some(granted) = lookup(&group.permissions, permission) {
+            return granted;
+        }
+        queue.extend(group.parents);
+    }
+
+    player.op_level >= MAX_OP_LEVEL
+}
+
+/// Finds the most specific node matching a permission, so `wyvern.build.*` wins over `*`.
+fn lookup(nodes: &HashMap<String, bool>, permission: &str) -> Option<bool> {
+    if let Some(granted) = nodes.get(permission) {
+        return Some(*granted);
+    }
+    let mut prefix = permission;
+    while let Some(end) = prefix.rfind('.') {
+        prefix = &prefix[..end];
+        if let Some(granted) = nodes.get(&format!("{}.*", prefix)) {
+            return Some(*granted);
+        }
+    }
+    nodes.get("*").copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use wyvern_values::Uuid;
+
+    use super::{
+        MemoryPermissionStorage, PermissionGroup, PermissionStorage, PlayerPermissions,
+        check_permission, op_level_group,
+    };
+
+    fn nodes(nodes: &[(&str, bool)]) -> HashMap<String, bool> {
+        nodes
+            .iter()
+            .map(|(node, granted)| (node.to_string(), *granted))
+            .collect()
+    }
+
+    #[test]
+    fn wildcards_prefer_specific_nodes() {
+        let storage = MemoryPermissionStorage::default();
+        let uuid = Uuid::nil();
+        storage.set_player(
+            uuid,
+            PlayerPermissions {
+                permissions: nodes(&[("wyvern.*", true), ("wyvern.build.*", false)]),
+                ..Default::default()
+            },
+        );
+        assert!(check_permission(&storage, uuid, "wyvern.fly"));
+        assert!(!check_permission(&storage, uuid, "wyvern.build.place"));
+        assert!(!check_permission(&storage, uuid, "other.node"));
+    }
+
+    #[test]
+    fn groups_and_op_levels() {
+        let storage = MemoryPermissionStorage::default();
+        let uuid = Uuid::nil();
+        storage.set_group(
+            "default",
+            PermissionGroup {
+                permissions: nodes(&[("wyvern.chat", true)]),
+                parents: Vec::new(),
+            },
+        );
+        storage.set_group(
+            "builder",
+            PermissionGroup {
+                permissions: nodes(&[("wyvern.build", true), ("wyvern.admin", false)]),
+                // Cycles between groups must not hang the check.
+                parents: vec!["builder".to_string(), "default".to_string()],
+            },
+        );
+        storage.set_player(
+            uuid,
+            PlayerPermissions {
+                op_level: 4,
+                groups: vec!["builder".to_string()],
+                permissions: HashMap::new(),
+            },
+        );
+        assert!(check_permission(&storage, uuid, "wyvern.chat"));
+        assert!(check_permission(&storage, uuid, "wyvern.build"));
+        assert!(!check_permission(&storage, uuid, "wyvern.admin"));
+        assert!(check_permission(&storage, uuid, "anything.else"));
+    }
+
+    #[test]
+    fn op_levels_inherit_lower_level_groups() {
+        let storage = MemoryPermissionStorage::default();
+        let uuid = Uuid::nil();
+        for (level, node) in [
+            (1, "wyvern.spawn"),
+            (2, "wyvern.gamemode"),
+            (3, "wyvern.kick"),
+        ] {
+            storage.set_group(
+                &op_level_group(level),
+                PermissionGroup {
+                    permissions: nodes(&[(node, true)]),
+                    parents: Vec::new(),
+                },
+            );
+        }
+        storage.set_player(
+            uuid,
+            PlayerPermissions {
+                op_level: 2,
+                ..Default::default()
+            },
+        );
+        assert!(check_permission(&storage, uuid, "wyvern.spawn"));
+        assert!(check_permission(&storage, uuid, "wyvern.gamemode"));
+        assert!(!check_permission(&storage, uuid, "wyvern.kick"));
+        assert!(!check_permission(&storage, uuid, "anything.else"));
+    }
+}
@@ -0,0 +1,202 @@
+use std::{
+    collections::HashMap,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::RwLock,
+};
+
+use json::JsonValue;
+use wyvern_values::Uuid;
+
+use super::{PermissionGroup, PermissionStorage, PlayerPermissions};
+
+/// Keeps permissions in memory. Anything set is lost when the server stops.
+#[derive(Debug, Default)]
+pub struct MemoryPermissionStorage {
+    players: RwLock<HashMap<Uuid, PlayerPermissions>>,
+    groups: RwLock<HashMap<String, PermissionGroup>>,
+}
+
+impl PermissionStorage for MemoryPermissionStorage {
+    fn player(&self, uuid: Uuid) -> PlayerPermissions {
+        self.players
+            .read()
+            .unwrap()
+            .get(&uuid)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn set_player(&self, uuid: Uuid, permissions: PlayerPermissions) {
+        self.players.write().unwrap().insert(uuid, permissions);
+    }
+
+    fn group(&self, name: &str) -> Option<PermissionGroup> {
+        self.groups.read().unwrap().get(name).cloned()
+    }
+
+    fn set_group(&self, name: &str, group: PermissionGroup) {
+        self.groups.write().unwrap().insert(name.to_string(), group);
+    }
+}
+
+/// Keeps permissions in a JSON file, which is rewritten every time a permission changes.
+///
+/// ```json
+/// {
+///     "groups": { "builder": { "permissions": { "wyvern.build": true }, "parents": [] } },
+///     "players": { "<uuid>": { "op_level": 0, "groups": ["builder"], "permissions": {} } }
+/// }
+/// ```
+#[derive(Debug)]
+pub struct JsonPermissionStorage {
+    path: PathBuf,
+    memory: MemoryPermissionStorage,
+}
+
+impl JsonPermissionStorage {
+    /// Loads permissions from the file at `path`, which is created once a permission is set.
+    pub fn open(path: impl AsRef<Path>) -> std::io::Result<JsonPermissionStorage> {
+        let path = path.as_ref().to_path_buf();
+        let memory = match std::fs::read_to_string(&path) {
+            Ok(contents) => {
+                let json = json::parse(&contents)
+                    .map_err(|err| std::io::Error::new(ErrorKind::InvalidData, err))?;
+                from_json(&json)
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => MemoryPermissionStorage::default(),
+            Err(err) => return Err(err),
+        };
+        Ok(JsonPermissionStorage { path, memory })
+    }
+
+    fn save(&self) {
+        let contents = to_json(&self.memory).pretty(4);
+        // Writing to a temporary file first means a crash can never leave a half-written file.
+        let temp = self.path.with_extension("json.tmp");
+        let result =
+            std::fs::write(&temp, contents).and_then(|()| std::fs::rename(&temp, &self.path));
+        if let Err(err) = result {
+            log::error!("Failed to save permissions to {:?}: {:?}", self.path, err);
+        }
+    }
+}
+
+impl PermissionStorage for JsonPermissionStorage {
+    fn player(&self, uuid: Uuid) -> PlayerPermissions {
+        self.memory.player(uuid)
+    }
+
+    fn set_player(&self, uuid: Uuid, permissions: PlayerPermissions) {
+        self.memory.set_player(uuid, permissions);
+        self.save();
+    }
+
+    fn group(&self, name: &str) -> Option<PermissionGroup> {
+        self.memory.group(name)
+    }
+
+    fn set_group(&self, name: &str, group: PermissionGroup) {
+        self.memory.set_group(name, group);
+        self.save();
+    }
+}
+
+fn from_json(json: &JsonValue) -> MemoryPermissionStorage {
+    let storage = MemoryPermissionStorage::default();
+    for (name, group) in json["groups"].entries() {
+        storage.set_group(
+            name,
+            PermissionGroup {
+                permissions: nodes_from_json(&group["permissions"]),
+                parents: strings_from_json(&group["parents"]),
+            },
+        );
+    }
+    for (uuid, player) in json["players"].entries() {
+        let Ok(uuid) = Uuid::parse_str(uuid) else {
+            log::warn!("Ignoring permissions of invalid UUID {:?}", uuid);
+            continue;
+        };
+        storage.set_player(
+            uuid,
+            PlayerPermissions {
+                op_level: player["op_level"].as_u8().unwrap_or(0),
+                groups: strings_from_json(&player["groups"]),
+                permissions: nodes_from_json(&player["permissions"]),
+            },
+        );
+    }
+    storage
+}
+
+fn to_json(storage: &MemoryPermissionStorage) -> JsonValue {
+    let mut json = JsonValue::new_object();
+    json["groups"] = JsonValue::new_object();
+    for (name, group) in storage.groups.read().unwrap().iter() {
+        let mut value = JsonValue::new_object();
+        value["permissions"] = nodes_to_json(&group.permissions);
+        value["parents"] = group.parents.clone().into();
+        json["groups"][name.as_str()] = value;
+    }
+    json["players"] = JsonValue::new_object();
+    for (uuid, player) in storage.players.read().unwrap().iter() {
+        let mut value = JsonValue::new_object();
+        value["op_level"] = player.op_level.into();
+        value["groups"] = player.groups.clone().into();
+        value["permissions"] = nodes_to_json(&player.permissions);
+        json["players"][uuid.to_string().as_str()] = value;
+    }
+    json
+}
+
+fn nodes_from_json(json: &JsonValue) -> HashMap<String, bool> {
+    json.entries()
+        .filter_map(|(node, granted)| Some((node.to_string(), granted.as_bool()?)))
+        .collect()
+}
+
+fn nodes_to_json(nodes: &HashMap<String, bool>) -> JsonValue {
+    let mut json = JsonValue::new_object();
+    for (node, granted) in nodes {
+        json[node.as_str()] = (*granted).into();
+    }
+    json
+}
+
+fn strings_from_json(json: &JsonValue) -> Vec<String> {
+    json.members()
+        .filter_map(|value| value.as_str().map(str::to_string))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use wyvern_values::Uuid;
+
+    use super::{MemoryPermissionStorage, from_json, to_json};
+    use crate::permissions::{PermissionGroup, PermissionStorage, PlayerPermissions};
+
+    #[test]
+    fn json_round_trip() {
+        let storage = MemoryPermissionStorage::default();
+        let uuid = Uuid::new_v4();
+        let player = PlayerPermissions {
+            op_level: 2,
+            groups: vec!["builder".to_string()],
+            permissions: HashMap::from([("wyvern.fly".to_string(), false)]),
+        };
+        let group = PermissionGroup {
+            permissions: HashMap::from([("wyvern.build.*".to_string(), true)]),
+            parents: vec!["default".to_string()],
+        };
+        storage.set_player(uuid, player.clone());
+        storage.set_group("builder", group.clone());
+
+        let loaded = from_json(&json::parse(&to_json(&storage).dump()).unwrap());
+        assert_eq!(loaded.player(uuid), player);
+        assert_eq!(loaded.group("builder"), Some(group));
+    }
+}
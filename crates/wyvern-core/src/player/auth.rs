@@ -0,0 +1,172 @@
+use std::{str::FromStr, time::Duration};
+
+use sha1::{Digest, Sha1};
+use voxidian_protocol::{
+    mojang::auth_verify::MojAuthError,
+    value::{ProfileProperty, Uuid},
+};
+use wyvern_datatypes::text::Text;
+
+pub(crate) const DEFAULT_SESSION_SERVER: &str = "https://sessionserver.mojang.com";
+/// How long connecting to the session server may take.
+const SESSION_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long a whole request to the session server may take, including connecting.
+const SESSION_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+pub(crate) struct AuthenticatedProfile {
+    pub(crate) uuid: Uuid,
+    pub(crate) name: String,
+    pub(crate) properties: Vec<ProfileProperty>,
+}
+
+/// Asks the session server whether the player has joined this server. This blocks until the
+/// session server responds or times out, so it must not run on a connection thread.
+pub(crate) fn has_joined(
+    session_server: &str,
+    username: &str,
+    server_id: &str,
+    shared_secret: &[u8],
+    public_key: &[u8],
+) -> Result<AuthenticatedProfile, MojAuthError> {
+    let hash = server_hash(server_id, shared_secret, public_key);
+    // A session server that stops responding counts as being down.
+    let agent = ureq::Agent::config_builder()
+        .timeout_connect(Some(SESSION_CONNECT_TIMEOUT))
+        .timeout_global(Some(SESSION_REQUEST_TIMEOUT))
+        .build()
+        .new_agent();
+    let mut response = agent
+        .get(format!(
+            "{}/session/minecraft/hasJoined",
+            session_server.trim_end_matches('/')
+        ))
+        .query("username", username)
+        .query("serverId", hash)
+        .call()
+        .map_err(|_| MojAuthError::AuthServerDown)?;
+
+    if response.status() == 204 {
+        return Err(MojAuthError::Unverified);
+    }
+
+    let body = response
+        .body_mut()
+        .read_to_string()
+        .map_err(|err| match err {
+            ureq::Error::Timeout(_) => MojAuthError::AuthServerDown,
+            _ => MojAuthError::InvalidData,
+        })?;
+    let json = json::parse(&body).map_err(|_| MojAuthError::InvalidData)?;
+
+    let uuid = json["id"]
+        .as_str()
+        .and_then(|x| Uuid::from_str(x).ok())
+        .ok_or(MojAuthError::InvalidData)?;
+    let name = json["name"]
+        .as_str()
+        .ok_or(MojAuthError::InvalidData)?
+        .to_string();
+    let properties = json["properties"]
+        .members()
+        .map(|prop| {
+            Some(ProfileProperty {
+                name: prop["name"].as_str()?.to_string(),
+                value: prop["value"].as_str()?.to_string(),
+                sig: prop["signature"].as_str().map(|x| x.to_string()),
+            })
+        })
+        .collect::<Option<Vec<_>>>()
+        .ok_or(MojAuthError::InvalidData)?;
+
+    Ok(AuthenticatedProfile {
+        uuid,
+        name,
+        properties,
+    })
+}
+
+pub(crate) fn auth_error_reason(error: &MojAuthError) -> Text {
+    match error {
+        MojAuthError::AuthServerDown => {
+            Text::literal("Authentication servers are down. Please try again later.")
+        }
+        MojAuthError::InvalidData => {
+            Text::literal("The authentication server sent an invalid response.")
+        }
+        MojAuthError::Unverified => Text::literal("Failed to verify username!"),
+    }
+}
+
+/// Whether a username is one vanilla clients can have: 1 to 16 letters, digits or underscores.
+pub(crate) fn is_valid_username(username: &str) -> bool {
+    (1..=16).contains(&username.len())
+        && username
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || byte == b'_')
+}
+
+/// Minecraft's server hash, a SHA-1 digest printed as a signed hexadecimal number.
+fn server_hash(server_id: &str, shared_secret: &[u8], public_key: &[u8]) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(server_id.as_bytes());
+    hasher.update(shared_secret);
+    hasher.update(public_key);
+    let mut digest: [u8; 20] = hasher.finalize().into();
+
+    let negative = digest[0] & 0x80 != 0;
+    if negative {
+        let mut carry = true;
+        for byte in digest.iter_mut().rev() {
+            *byte = !*byte;
+            if carry {
+                let (value, overflow) = byte.overflowing_add(1);
+                *byte = value;
+                carry = overflow;
+            }
+        }
+    }
+
+    let hex = digest
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+    let hex = hex.trim_start_matches('0');
+    if negative {
+        format!("-{hex}")
+    } else {
+        hex.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_valid_username, server_hash};
+
+    #[test]
+    fn server_hashes() {
+        assert_eq!(
+            server_hash("Notch", &[], &[]),
+            "4ed1f46bbe04bc756bcb17c0c7ce3e4632f06a48"
+        );
+        assert_eq!(
+            server_hash("jeb_", &[], &[]),
+            "-7c9d5b0044c130109a5d7b5fb5c317c02b4e28c1"
+        );
+        assert_eq!(
+            server_hash("simon", &[], &[]),
+            "88e16a1019277b15d58faf0541e11910eb756f6"
+        );
+    }
+
+    #[test]
+    fn usernames() {
+        assert!(is_valid_username("Notch"));
+        assert!(is_valid_username("jeb_"));
+        assert!(is_valid_username("abcdefghijklmnop"));
+        assert!(!is_valid_username(""));
+        assert!(!is_valid_username("abcdefghijklmnopq"));
+        assert!(!is_valid_username("a&serverId=0"));
+        assert!(!is_valid_username("Stéve"));
+    }
+}
@@ -0,0 +1,88 @@
+use voxidian_protocol::packet::{
+    Stage,
+    s2c::{config::CustomPayloadS2CConfigPacket, play::CustomPayloadS2CPlayPacket},
+};
+use wyvern_components::DataComponentHolder;
+use wyvern_values::Id;
+
+use crate::{
+    actors::ActorResult,
+    events::PluginMessageEvent,
+    varint::{PayloadReader, write_string},
+};
+
+use super::{ConnectionData, PlayerComponents};
+
+pub const BRAND_CHANNEL: Id = Id::constant("minecraft", "brand");
+pub const REGISTER_CHANNEL: Id = Id::constant("minecraft", "register");
+pub const UNREGISTER_CHANNEL: Id = Id::constant("minecraft", "unregister");
+
+/// The largest plugin message payload the client accepts, in bytes.
+pub const MAX_PLUGIN_MESSAGE_SIZE: usize = 1048576;
+
+pub(crate) const SERVER_BRAND: &str = "WyvernMC";
+
+impl ConnectionData {
+    /// Tells the client the server's brand and which plugin channels it listens on.
+    pub(crate) fn send_server_channels(&mut self) -> ActorResult<()> {
+        let mut brand = Vec::new();
+        write_string(&mut brand, SERVER_BRAND);
+        self.write_plugin_message(BRAND_CHANNEL, brand);
+
+        let channels = self.connected_server.plugin_channels()?;
+        if !channels.is_empty() {
+            self.write_plugin_message(REGISTER_CHANNEL, encode_channel_list(&channels));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn write_plugin_message(&mut self, channel: Id, data: Vec<u8>) {
+        let stage = *self.stage.lock().unwrap();
+        match stage {
+            Stage::Config => self.write_packet(CustomPayloadS2CConfigPacket {
+                channel: channel.into(),
+                data: data.into(),
+            }),
+            Stage::Play => self.write_packet(CustomPayloadS2CPlayPacket {
+                channel: channel.into(),
+                data: data.into(),
+            }),
+            _ => {}
+        }
+    }
+
+    pub(crate) fn receive_plugin_message(&mut self, channel: Id, data: Vec<u8>) -> ActorResult<()> {
+        if channel == BRAND_CHANNEL {
+            if let Some(brand) = PayloadReader::new(&data).string() {
+                self.set(PlayerComponents::CLIENT_BRAND, brand);
+            }
+            return Ok(());
+        }
+        if channel == REGISTER_CHANNEL || channel == UNREGISTER_CHANNEL {
+            return Ok(());
+        }
+
+        if !self.connected_server.plugin_channels()?.contains(&channel) {
+            log::debug!(
+                "Ignoring plugin message on unregistered channel {:?}",
+                channel
+            );
+            return Ok(());
+        }
+        self.connected_server.spawn_event(PluginMessageEvent {
+            player: self.as_actor(),
+            channel,
+            data,
+        })
+    }
+}
+
+/// Encodes a list of channels for `minecraft:register`, separated by null bytes.
+fn encode_channel_list(channels: &[Id]) -> Vec<u8> {
+    channels
+        .iter()
+        .map(|channel| channel.to_string())
+        .collect::<Vec<_>>()
+        .join("\0")
+        .into_bytes()
+}
@@ -33,6 +33,8 @@ impl PlayerComponents {
         DataComponentType::new(id![minecraft:input_flags]);
     pub const GAMEMODE: DataComponentType<Gamemode> =
         DataComponentType::new(id![minecraft:gamemode]);
+    pub const CLIENT_BRAND: DataComponentType<String> =
+        DataComponentType::new(id![minecraft:client_brand]);
 
     pub const TELEPORT_SYNC_SENT: DataComponentType<i32> =
         DataComponentType::new(id![minecraft:teleport_sent]);
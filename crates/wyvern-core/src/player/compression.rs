@@ -0,0 +1,159 @@
+use std::io::{Read, Write};
+
+use flate2::{read::ZlibDecoder, write::ZlibEncoder};
+use voxidian_protocol::packet::PacketBuf;
+
+use crate::{
+    actors::{ActorError, ActorResult},
+    varint::{read_varint, write_varint},
+};
+
+use super::ConnectionData;
+
+/// The largest size a compressed packet may claim to have once decompressed.
+const MAX_DECOMPRESSED_SIZE: usize = 8388608;
+
+/// How packets are compressed on a connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    /// Packets are never compressed. Useful when the server is only reachable over a local
+    /// network, where bandwidth is cheaper than CPU time.
+    Disabled,
+    /// Packets of at least `threshold` bytes are compressed with zlib at the given `level`,
+    /// from 0 (no compression) to 9 (best compression).
+    ZLib { threshold: usize, level: u32 },
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression::ZLib {
+            threshold: 128,
+            level: 6,
+        }
+    }
+}
+
+impl Compression {
+    pub(crate) fn threshold(&self) -> Option<usize> {
+        match self {
+            Compression::Disabled => None,
+            Compression::ZLib { threshold, .. } => Some(*threshold),
+        }
+    }
+}
+
+impl ConnectionData {
+    pub(crate) fn compress_packet(&self, buf: PacketBuf) -> ActorResult<PacketBuf> {
+        if self.compression == Compression::Disabled {
+            return Ok(buf);
+        }
+        let data = buf.iter().collect::<Vec<u8>>();
+        let compressed = compress(self.compression, &data).map_err(|_| ActorError::BadRequest)?;
+
+        let mut buf = PacketBuf::new();
+        buf.write_u8s(&compressed);
+        Ok(buf)
+    }
+}
+
+/// Undoes the compression framing of a received packet.
+/// Returns `None` if the packet is malformed or decompresses to too many bytes.
+pub(crate) fn decompress_packet(compression: Compression, buf: PacketBuf) -> Option<PacketBuf> {
+    if compression == Compression::Disabled {
+        return Some(buf);
+    }
+    let data = buf.iter().collect::<Vec<u8>>();
+    let decompressed = decompress(&data)?;
+
+    let mut buf = PacketBuf::new();
+    buf.write_u8s(&decompressed);
+    Some(buf)
+}
+
+/// Frames a packet for a connection with compression enabled. The packet is prefixed with its
+/// uncompressed length, or 0 if it is below the threshold and was left uncompressed.
+fn compress(compression: Compression, data: &[u8]) -> std::io::Result<Vec<u8>> {
+    let Compression::ZLib { threshold, level } = compression else {
+        return Ok(data.to_vec());
+    };
+
+    let mut framed = Vec::with_capacity(data.len() + 5);
+    if data.len() < threshold {
+        write_varint(&mut framed, 0);
+        framed.extend_from_slice(data);
+        return Ok(framed);
+    }
+
+    write_varint(&mut framed, data.len() as i32);
+    let mut encoder = ZlibEncoder::new(framed, flate2::Compression::new(level.min(9)));
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+fn decompress(data: &[u8]) -> Option<Vec<u8>> {
+    let (size, read) = read_varint(data.iter().copied())?;
+    let size = usize::try_from(size).ok()?;
+    let rest = &data[read..];
+    if size == 0 {
+        return Some(rest.to_vec());
+    }
+    if size > MAX_DECOMPRESSED_SIZE {
+        return None;
+    }
+
+    let mut decompressed = Vec::with_capacity(size);
+    ZlibDecoder::new(rest)
+        .take(size as u64 + 1)
+        .read_to_end(&mut decompressed)
+        .ok()?;
+    (decompressed.len() == size).then_some(decompressed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Compression, compress, decompress};
+
+    #[test]
+    fn below_threshold_is_uncompressed() {
+        let framed = compress(
+            Compression::ZLib {
+                threshold: 64,
+                level: 6,
+            },
+            &[1, 2, 3],
+        )
+        .unwrap();
+        assert_eq!(framed, [0, 1, 2, 3]);
+        assert_eq!(decompress(&framed).unwrap(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn round_trip_at_every_level() {
+        let data = (0..1000).map(|i| (i % 7) as u8).collect::<Vec<u8>>();
+        for level in 0..=9 {
+            let framed = compress(
+                Compression::ZLib {
+                    threshold: 64,
+                    level,
+                },
+                &data,
+            )
+            .unwrap();
+            assert_eq!(decompress(&framed).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn wrong_size_is_rejected() {
+        let mut framed = compress(
+            Compression::ZLib {
+                threshold: 0,
+                level: 6,
+            },
+            &[5; 100],
+        )
+        .unwrap();
+        framed[0] = 99;
+        assert!(decompress(&framed).is_none());
+    }
+}
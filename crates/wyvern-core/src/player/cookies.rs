@@ -0,0 +1,60 @@
+use std::time::{Duration, Instant};
+
+use wyvern_values::Id;
+
+use crate::actors::ActorResult;
+
+use super::{ConnectionData, Player};
+
+/// The largest cookie payload the client accepts, in bytes.
+pub const MAX_COOKIE_SIZE: usize = 5120;
+/// How long the client has to answer a cookie request.
+pub(crate) const COOKIE_TIMEOUT: Duration = Duration::from_secs(10);
+
+impl Player {
+    /// Asks the client for the cookie stored under `id`.
+    /// Resolves to `None` if the client has no cookie with that id, does not answer within
+    /// 10 seconds, or disconnects.
+    pub async fn request_cookie(&self, id: Id) -> ActorResult<Option<Vec<u8>>> {
+        let response = self.request_cookie_internal(id)?;
+        // The request is dropped without an answer once it expires or the player leaves.
+        Ok(response.recv_async().await.ok().flatten())
+    }
+}
+
+impl ConnectionData {
+    pub(crate) fn receive_cookie(&mut self, id: Id, payload: Option<Vec<u8>>) {
+        let Some((_, requests)) = self.pending_cookies.remove(&id) else {
+            log::warn!(
+                "Received cookie {:?} from {:?} without requesting it",
+                id,
+                self.addr
+            );
+            return;
+        };
+        for request in requests {
+            let _ = request.send(payload.clone());
+        }
+    }
+
+    /// Gives up on cookie requests the client has not answered in time.
+    pub(crate) fn expire_cookie_requests(&mut self) {
+        self.pending_cookies.retain(|id, (requested, _)| {
+            let expired = requested.elapsed() >= COOKIE_TIMEOUT;
+            if expired {
+                log::debug!("Cookie request {:?} timed out", id);
+            }
+            !expired
+        });
+    }
+
+    /// The time left until the oldest cookie request expires.
+    pub(crate) fn until_cookie_timeout(&self) -> Option<Duration> {
+        self.pending_cookies
+            .values()
+            .map(|(requested, _)| {
+                (*requested + COOKIE_TIMEOUT).saturating_duration_since(Instant::now())
+            })
+            .min()
+    }
+}
@@ -1,4 +1,4 @@
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use crate::{dimension::Dimension, inventory::DataInventory, item::ItemStack};
 use wyvern_datatypes::window::InventoryKind;
@@ -16,6 +16,8 @@ pub struct PlayerData {
     pub(crate) entity_id: i32,
 
     pub(crate) last_sent_keep_alive: Instant,
+    pub(crate) pending_keep_alive: Option<i64>,
+    pub(crate) latency: Duration,
 
     pub(crate) inventory: DataInventory,
     pub(crate) screen: Option<(InventoryKind, DataInventory)>,
@@ -23,6 +25,9 @@ pub struct PlayerData {
     pub(crate) held_slot: i16,
 
     pub(crate) cursor_item: ItemStack,
+
+    pub(crate) protocol_version: i32,
+    pub(crate) transferred: bool,
 }
 
 impl Default for PlayerData {
@@ -36,6 +41,8 @@ impl Default for PlayerData {
             render_distance: 2,
             entity_id: 0,
             last_sent_keep_alive: Instant::now(),
+            pending_keep_alive: None,
+            latency: Duration::ZERO,
 
             inventory: DataInventory::new_filled(36, ItemStack::air),
 
@@ -44,6 +51,9 @@ impl Default for PlayerData {
 
             cursor_item: ItemStack::air(),
             window_id: 0,
+
+            protocol_version: 0,
+            transferred: false,
         }
     }
 }
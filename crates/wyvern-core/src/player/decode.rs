@@ -0,0 +1,83 @@
+use std::collections::VecDeque;
+
+use voxidian_protocol::packet::{DecodeError, PrefixedPacketDecode, processing::PacketProcessing};
+
+use crate::varint::read_varint;
+
+use super::{Compression, decompress_packet};
+
+/// The largest packet a client may send, which is also the largest length that fits in the
+/// 3 byte length prefix vanilla allows.
+pub(crate) const MAX_PACKET_SIZE: usize = 2097151;
+
+/// Why a packet sent by a client could not be read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum MalformedPacket {
+    /// The packet's length prefix is larger than `MAX_PACKET_SIZE` or is not a valid VarInt.
+    TooLarge,
+    /// The packet could not be split off from the byte stream.
+    BadFrame,
+    /// The packet's compression framing is invalid.
+    BadCompression,
+    /// The packet ended before all of its fields were read. Contains the packet's length.
+    Truncated(usize),
+    /// The packet's contents are invalid.
+    BadContents,
+}
+
+/// Reads the next packet out of `queue`, removing its bytes.
+/// Returns `Ok(None)` if the queue does not contain a whole packet yet.
+pub(crate) fn next_packet<T: PrefixedPacketDecode>(
+    processing: &mut PacketProcessing,
+    compression: Compression,
+    queue: &mut VecDeque<u8>,
+) -> Result<Option<T>, MalformedPacket> {
+    check_length(queue)?;
+
+    let (buf, consumed) = match processing.decode_from_raw_queue(queue.iter().copied()) {
+        Ok((buf, consumed)) => (buf, consumed),
+        Err(DecodeError::EndOfBuffer) => return Ok(None),
+        Err(_) => return Err(MalformedPacket::BadFrame),
+    };
+    if consumed == 0 {
+        return Ok(None);
+    }
+    queue.drain(..consumed.min(queue.len()));
+
+    let mut buf = decompress_packet(compression, buf).ok_or(MalformedPacket::BadCompression)?;
+    let length = buf.as_slice().len();
+    match T::decode_prefixed(&mut buf) {
+        Ok(packet) => Ok(Some(packet)),
+        Err(DecodeError::EndOfBuffer) => Err(MalformedPacket::Truncated(length)),
+        Err(_) => Err(MalformedPacket::BadContents),
+    }
+}
+
+/// Rejects packets whose length prefix is too large before any of their bytes are buffered.
+fn check_length(queue: &VecDeque<u8>) -> Result<(), MalformedPacket> {
+    // Vanilla allows at most 3 bytes for the length prefix.
+    match read_varint(queue.iter().copied().take(3)) {
+        Some((length, _)) if length as usize > MAX_PACKET_SIZE => Err(MalformedPacket::TooLarge),
+        Some(_) => Ok(()),
+        None if queue.len() >= 3 => Err(MalformedPacket::TooLarge),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::{MalformedPacket, check_length};
+
+    #[test]
+    fn length_prefix() {
+        assert_eq!(check_length(&VecDeque::from([0x10, 0x00])), Ok(()));
+        assert_eq!(check_length(&VecDeque::from([0xFF, 0xFF])), Ok(()));
+        assert_eq!(check_length(&VecDeque::from([0xFF, 0xFF, 0x7F])), Ok(()));
+        assert_eq!(
+            check_length(&VecDeque::from([0xFF, 0xFF, 0xFF, 0x01])),
+            Err(MalformedPacket::TooLarge)
+        );
+    }
+}
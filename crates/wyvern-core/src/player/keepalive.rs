@@ -0,0 +1,75 @@
+use std::time::{Duration, Instant};
+
+use voxidian_protocol::packet::{Stage, s2c::play::KeepAliveS2CPlayPacket};
+use wyvern_components::DataComponentHolder;
+use wyvern_datatypes::text::Text;
+
+use crate::actors::ActorResult;
+
+use super::{ConnectionData, PlayerComponents};
+
+/// How often keep alives are sent to players.
+pub(crate) const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(5);
+/// How long a player has to answer a keep alive by default.
+pub(crate) const DEFAULT_KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(30);
+
+impl ConnectionData {
+    /// Sends a keep alive when one is due, and disconnects the player if the last one went
+    /// unanswered for too long.
+    pub(crate) fn tick_keep_alive(&mut self) -> ActorResult<()> {
+        if *self.stage.lock().unwrap() != Stage::Play {
+            return Ok(());
+        }
+
+        let since_sent = self.associated_data.last_sent_keep_alive.elapsed();
+        if self.associated_data.pending_keep_alive.is_some() {
+            if since_sent > self.keep_alive_timeout {
+                log::info!(
+                    "{:?} did not respond to a keep alive within {:?}",
+                    self.get(PlayerComponents::USERNAME)?,
+                    self.keep_alive_timeout
+                );
+                self.kick(Text::literal("Timed out"))?;
+            }
+            return Ok(());
+        }
+
+        if since_sent >= KEEP_ALIVE_INTERVAL {
+            let id = rand::random::<i64>();
+            self.write_packet(KeepAliveS2CPlayPacket(id));
+            self.associated_data.pending_keep_alive = Some(id);
+            self.associated_data.last_sent_keep_alive = Instant::now();
+        }
+        Ok(())
+    }
+
+    /// The time left until `tick_keep_alive` has something to do.
+    pub(crate) fn until_keep_alive(&self) -> Duration {
+        let wait = if self.associated_data.pending_keep_alive.is_some() {
+            self.keep_alive_timeout
+        } else {
+            KEEP_ALIVE_INTERVAL
+        };
+        (self.associated_data.last_sent_keep_alive + wait).saturating_duration_since(Instant::now())
+    }
+
+    pub(crate) fn receive_keep_alive(&mut self, id: i64) -> ActorResult<()> {
+        if self.associated_data.pending_keep_alive != Some(id) {
+            log::debug!("Received an unexpected keep alive from {:?}", self.addr);
+            return Ok(());
+        }
+        self.associated_data.pending_keep_alive = None;
+
+        // Smoothed the same way as vanilla, so a single slow response doesn't cause a spike.
+        let sample = self.associated_data.last_sent_keep_alive.elapsed();
+        let latency = &mut self.associated_data.latency;
+        *latency = (*latency * 3 + sample) / 4;
+        let latency = latency.as_millis() as i32;
+
+        // Sending the latency to every player right away would cost a packet per pair of
+        // players on every keep alive, so the server batches them up until the next tick.
+        self.connected_server
+            .update_latency(self.get(PlayerComponents::UUID)?, latency)?;
+        Ok(())
+    }
+}
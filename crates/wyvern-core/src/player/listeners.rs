@@ -0,0 +1,149 @@
+use std::{fmt::Debug, net::IpAddr};
+
+use voxidian_protocol::packet::{
+    PacketBuf, Stage,
+    c2s::{
+        config::C2SConfigPackets, handshake::C2SHandshakePackets, login::C2SLoginPackets,
+        play::C2SPlayPackets, status::C2SStatusPackets,
+    },
+};
+
+use crate::varint::read_varint;
+
+use super::{ConnectionData, Player};
+
+/// Whether an intercepted packet should continue on its way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketDecision {
+    Pass,
+    Cancel,
+}
+
+/// The connection a packet was intercepted on.
+///
+/// Packet listeners run on the connection's own thread, so they must not block or call
+/// methods on `player` directly. Spawn a task with `Runtime::spawn_task` instead.
+#[derive(Debug, Clone)]
+pub struct PacketContext {
+    pub player: Player,
+    pub addr: IpAddr,
+    pub stage: Stage,
+}
+
+/// An encoded packet about to be sent to a client.
+#[derive(Debug)]
+pub struct OutgoingPacket {
+    pub stage: Stage,
+    /// The packet's id followed by its data, before compression and encryption.
+    pub buf: PacketBuf,
+}
+
+impl OutgoingPacket {
+    pub fn packet_id(&self) -> Option<i32> {
+        read_varint(self.buf.iter()).map(|(id, _)| id)
+    }
+}
+
+pub type InboundListener<T> = Box<dyn Fn(&PacketContext, &mut T) -> PacketDecision + Send + Sync>;
+pub type OutboundListener =
+    Box<dyn Fn(&PacketContext, &mut OutgoingPacket) -> PacketDecision + Send + Sync>;
+
+/// Listeners that see packets before the server handles or sends them.
+#[derive(Default)]
+pub struct PacketListeners {
+    handshake: Vec<InboundListener<C2SHandshakePackets>>,
+    status: Vec<InboundListener<C2SStatusPackets>>,
+    login: Vec<InboundListener<C2SLoginPackets>>,
+    config: Vec<InboundListener<C2SConfigPackets>>,
+    play: Vec<InboundListener<C2SPlayPackets>>,
+    outbound: Vec<OutboundListener>,
+}
+
+impl Debug for PacketListeners {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("PacketListeners { ... }")
+    }
+}
+
+impl PacketListeners {
+    pub(crate) fn add_inbound<T: InboundPacket>(&mut self, listener: InboundListener<T>) {
+        T::listeners_mut(self).push(listener);
+    }
+
+    pub(crate) fn add_outbound(&mut self, listener: OutboundListener) {
+        self.outbound.push(listener);
+    }
+}
+
+/// A packet sent by the client that can be intercepted, one per protocol stage.
+pub trait InboundPacket: Sized + 'static {
+    fn listeners(listeners: &PacketListeners) -> &[InboundListener<Self>];
+    fn listeners_mut(listeners: &mut PacketListeners) -> &mut Vec<InboundListener<Self>>;
+}
+
+macro_rules! inbound_packets {
+    ($($name:ident: $ty:ty)*) => {
+        $(impl InboundPacket for $ty {
+            fn listeners(listeners: &PacketListeners) -> &[InboundListener<Self>] {
+                &listeners.$name
+            }
+
+            fn listeners_mut(listeners: &mut PacketListeners) -> &mut Vec<InboundListener<Self>> {
+                &mut listeners.$name
+            }
+        })*
+    };
+}
+
+inbound_packets! {
+    handshake: C2SHandshakePackets
+    status: C2SStatusPackets
+    login: C2SLoginPackets
+    config: C2SConfigPackets
+    play: C2SPlayPackets
+}
+
+impl ConnectionData {
+    fn packet_context(&self) -> PacketContext {
+        PacketContext {
+            player: self.as_actor(),
+            addr: self.addr,
+            stage: *self.stage.lock().unwrap(),
+        }
+    }
+
+    /// Runs the inbound listeners for a decoded packet. Listeners may rewrite the packet.
+    pub(crate) fn intercept_incoming<T: InboundPacket>(&self, packet: &mut T) -> PacketDecision {
+        let listeners = T::listeners(&self.packet_listeners);
+        if listeners.is_empty() {
+            return PacketDecision::Pass;
+        }
+
+        let context = self.packet_context();
+        for listener in listeners {
+            if listener(&context, packet) == PacketDecision::Cancel {
+                return PacketDecision::Cancel;
+            }
+        }
+        PacketDecision::Pass
+    }
+
+    /// Runs the outbound listeners for an encoded packet, returning `None` if it was cancelled.
+    pub(crate) fn intercept_outgoing(&self, buf: PacketBuf) -> Option<PacketBuf> {
+        if self.packet_listeners.outbound.is_empty() {
+            return Some(buf);
+        }
+
+        let context = self.packet_context();
+        let mut packet = OutgoingPacket {
+            stage: context.stage,
+            buf,
+        };
+        for listener in &self.packet_listeners.outbound {
+            if listener(&context, &mut packet) == PacketDecision::Cancel {
+                return None;
+            }
+        }
+        Some(packet.buf)
+    }
+}
@@ -1,29 +1,34 @@
 use std::{
-    collections::VecDeque,
-    net::{IpAddr, TcpStream},
+    collections::{HashMap, VecDeque},
+    net::IpAddr,
     sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 
 use data::PlayerData;
-use flume::{Receiver, Sender};
+use flume::{Receiver, Sender, TryRecvError};
 use inventory::PlayerInventory;
 use net::ConnectionStoppedSignal;
 use voxidian_protocol::{
-    mojang::auth_verify::MojAuthProperty,
+    mojang::auth_verify::MojAuthError,
     packet::{
         PacketBuf, PacketEncode, PrefixedPacketEncode, Stage,
         processing::{PacketProcessing, PrivateKey, PublicKey},
         s2c::{
-            config::DisconnectS2CConfigPacket,
-            login::LoginDisconnectS2CLoginPacket,
+            config::{
+                CookieRequestS2CConfigPacket, DisconnectS2CConfigPacket,
+                StoreCookieS2CConfigPacket, TransferS2CConfigPacket,
+            },
+            login::{CookieRequestS2CLoginPacket, LoginDisconnectS2CLoginPacket},
             play::{
-                AddEntityS2CPlayPacket, ContainerSetSlotS2CPlayPacket, DisconnectS2CPlayPacket,
-                ForgetLevelChunkS2CPlayPacket, GameEvent, GameEventS2CPlayPacket,
-                Gamemode as PtcGamemode, LevelParticlesS2CPlayPacket, OpenScreenS2CPlayPacket,
-                PlayerPositionS2CPlayPacket, PlayerRotationS2CPlayPacket,
+                AddEntityS2CPlayPacket, ContainerSetSlotS2CPlayPacket, CookieRequestS2CPlayPacket,
+                DisconnectS2CPlayPacket, ForgetLevelChunkS2CPlayPacket, GameEvent,
+                GameEventS2CPlayPacket, Gamemode as PtcGamemode, LevelParticlesS2CPlayPacket,
+                OpenScreenS2CPlayPacket, PlayerPositionS2CPlayPacket, PlayerRotationS2CPlayPacket,
                 RemoveEntitiesS2CPlayPacket, RespawnDataKept, RespawnS2CPlayPacket,
                 ScreenWindowKind, SetEntityDataS2CPlayPacket, SoundCategory,
-                SoundEntityS2CPlayPacket, SystemChatS2CPlayPacket, TeleportFlags,
+                SoundEntityS2CPlayPacket, StoreCookieS2CPlayPacket, SystemChatS2CPlayPacket,
+                TeleportFlags, TransferS2CPlayPacket,
             },
         },
     },
@@ -36,11 +41,13 @@ use wyvern_macros::{actor, message};
 
 use crate::{
     actors::{ActorError, ActorResult},
+    commands::{CommandRegistry, suggestions_packet},
     dimension::Dimension,
     entities::EntityComponents,
     inventory::{DataInventory, Inventory},
     item::ItemStack,
-    server::{Server, registries::RegistryKeys},
+    permissions::{MAX_OP_LEVEL, PermissionStorage},
+    server::{ForwardedPlayer, NetworkReactor, Server, registries::RegistryKeys},
 };
 
 use wyvern_values::{DVec3, Id, Uuid, Vec2};
@@ -48,28 +55,61 @@ use wyvern_values::{DVec3, Id, Uuid, Vec2};
 mod components;
 pub use components::*;
 
+mod auth;
+pub(crate) use auth::*;
+mod channels;
+pub use channels::*;
 pub mod chunkload;
+mod compression;
+pub use compression::*;
+mod decode;
+pub(crate) use decode::*;
+mod cookies;
+pub use cookies::*;
 pub mod data;
 pub mod inventory;
 pub mod itf;
+mod keepalive;
+pub(crate) use keepalive::*;
+mod listeners;
+pub use listeners::*;
 pub mod net;
+mod outbound;
+pub(crate) use outbound::*;
+mod permissions;
+mod profile;
+pub use profile::*;
 pub mod skins;
 pub mod stages;
 
 #[actor(Player, PlayerMessage)]
 pub(crate) struct ConnectionData {
-    pub(crate) stream: TcpStream,
-    #[allow(dead_code)]
+    pub(crate) stream: mio::net::TcpStream,
+    pub(crate) reactor: Arc<NetworkReactor>,
+    pub(crate) reactor_token: mio::Token,
+    pub(crate) wake: Receiver<()>,
     pub(crate) addr: IpAddr,
     pub(crate) received_bytes: VecDeque<u8>,
-    pub(crate) bytes_to_send: Vec<u8>,
+    pub(crate) outbound: OutboundBuffer,
     pub(crate) packet_processing: PacketProcessing,
     pub(crate) signal: Sender<ConnectionStoppedSignal>,
     pub(crate) connected_server: Server,
     pub(crate) stage: Arc<Mutex<Stage>>,
     pub(crate) associated_data: PlayerData,
     pub(crate) sender: Sender<PlayerMessage>,
+    pub(crate) login: LoginState,
     pub(crate) mojauth: Option<MojauthData>,
+    pub(crate) forwarded: Option<ForwardedPlayer>,
+    pub(crate) velocity_query_id: Option<i32>,
+    pub(crate) profile_properties: Vec<ProfileProperty>,
+    pub(crate) packet_listeners: Arc<PacketListeners>,
+    pub(crate) commands: Arc<CommandRegistry>,
+    pub(crate) permissions: Arc<dyn PermissionStorage>,
+    pub(crate) compression: Compression,
+    pub(crate) keep_alive_timeout: Duration,
+    /// Cookie requests waiting for the client, with when the cookie was first requested.
+    pub(crate) pending_cookies: HashMap<Id, (Instant, Vec<Sender<Option<Vec<u8>>>>)>,
+    pub(crate) closing: bool,
     pub(crate) last_saved_components: DataComponentMap,
     pub(crate) components: DataComponentMap,
 }
@@ -84,11 +124,25 @@ impl DataComponentHolder for ConnectionData {
     }
 }
 
+/// How far the client has got through logging in, so login packets sent out of order can
+/// be rejected.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) enum LoginState {
+    #[default]
+    AwaitingHello,
+    /// Waiting for the proxy to answer the Velocity query, with the name the client sent.
+    AwaitingForwarding(String),
+    /// Waiting for the client's encryption response, with the name the client sent.
+    AwaitingKey(String),
+    /// Waiting for the session server to confirm the player's identity.
+    Authenticating,
+    Finished,
+}
+
 pub(crate) struct MojauthData {
     pub(crate) public_key: Option<PublicKey>,
     pub(crate) private_key: Option<PrivateKey>,
     pub(crate) verify_token: Vec<u8>,
-    pub(crate) props: Vec<MojAuthProperty>,
 }
 
 #[message(Player, PlayerMessage)]
@@ -162,6 +216,110 @@ impl ConnectionData {
         }
     }
 
+    #[Kick]
+    #[doc = "Disconnects this player with the given reason and closes their connection."]
+    pub fn kick(&mut self, message: Text) -> ActorResult<()> {
+        self.disconnect_internal(message)?;
+        self.closing = true;
+        Ok(())
+    }
+
+    #[Transfer]
+    #[doc = "Sends this player to another server, which must accept transfers. Cookies stored on the client are kept."]
+    pub fn transfer(&mut self, host: String, port: u16) -> ActorResult<()> {
+        let port = VarInt::from(port as i32);
+        let stage = *self.stage.lock().unwrap();
+        match stage {
+            Stage::Config => self.write_packet(TransferS2CConfigPacket { host, port }),
+            Stage::Play => self.write_packet(TransferS2CPlayPacket { host, port }),
+            _ => return Err(ActorError::BadRequest),
+        }
+        Ok(())
+    }
+
+    #[SendPluginMessage]
+    #[doc = "Sends a plugin message to this player's client on the given channel."]
+    pub fn send_plugin_message(&mut self, channel: Id, data: Vec<u8>) -> ActorResult<()> {
+        if data.len() > MAX_PLUGIN_MESSAGE_SIZE {
+            return Err(ActorError::BadRequest);
+        }
+        let stage = *self.stage.lock().unwrap();
+        if stage != Stage::Config && stage != Stage::Play {
+            return Err(ActorError::BadRequest);
+        }
+        self.write_plugin_message(channel, data);
+        Ok(())
+    }
+
+    #[GetLatency]
+    #[doc = "Returns this player's round trip latency, measured through keep alives."]
+    pub fn latency(&self) -> ActorResult<Duration> {
+        Ok(self.associated_data.latency)
+    }
+
+    #[WasTransferred]
+    #[doc = "Returns whether this player connected through a transfer from another server."]
+    pub fn was_transferred(&self) -> ActorResult<bool> {
+        Ok(self.associated_data.transferred)
+    }
+
+    #[StoreCookie]
+    #[doc = "Stores a cookie on the client. Cookies persist across transfers, but not across separate connections."]
+    pub fn store_cookie(&mut self, id: Id, data: Vec<u8>) -> ActorResult<()> {
+        if data.len() > MAX_COOKIE_SIZE {
+            return Err(ActorError::BadRequest);
+        }
+        let stage = *self.stage.lock().unwrap();
+        match stage {
+            Stage::Config => self.write_packet(StoreCookieS2CConfigPacket {
+                key: id.into(),
+                payload: data.into(),
+            }),
+            Stage::Play => self.write_packet(StoreCookieS2CPlayPacket {
+                key: id.into(),
+                payload: data.into(),
+            }),
+            _ => return Err(ActorError::BadRequest),
+        }
+        Ok(())
+    }
+
+    #[RequestCookieInternal]
+    pub(crate) fn request_cookie_internal(
+        &mut self,
+        id: Id,
+    ) -> ActorResult<Receiver<Option<Vec<u8>>>> {
+        let stage = *self.stage.lock().unwrap();
+        match stage {
+            Stage::Login => self.write_packet(CookieRequestS2CLoginPacket {
+                key: id.clone().into(),
+            }),
+            Stage::Config => self.write_packet(CookieRequestS2CConfigPacket {
+                key: id.clone().into(),
+            }),
+            Stage::Play => self.write_packet(CookieRequestS2CPlayPacket {
+                key: id.clone().into(),
+            }),
+            _ => return Err(ActorError::BadRequest),
+        }
+
+        let (tx, rx) = flume::bounded(1);
+        self.pending_cookies
+            .entry(id)
+            .or_insert_with(|| (Instant::now(), Vec::new()))
+            .1
+            .push(tx);
+        Ok(rx)
+    }
+
+    #[CompleteAuth]
+    pub(crate) fn complete_auth(
+        &mut self,
+        result: Result<AuthenticatedProfile, MojAuthError>,
+    ) -> ActorResult<()> {
+        self.apply_auth_result(result)
+    }
+
     #[SetStage]
     pub fn set_stage(&mut self, stage: Stage) -> ActorResult<()> {
         *self.stage.lock().unwrap() = stage;
@@ -175,8 +333,15 @@ impl ConnectionData {
 
     #[SendPacketBuf]
     pub(crate) fn send_packet_buf(&mut self, buf: PacketBuf) -> ActorResult<()> {
-        let cipherdata = self.packet_processing.encode_encrypt(buf).unwrap();
-        self.bytes_to_send.extend(cipherdata.as_slice());
+        let Some(buf) = self.intercept_outgoing(buf) else {
+            return Ok(());
+        };
+        let buf = self.compress_packet(buf)?;
+        let cipherdata = self.packet_processing.encode_encrypt(buf).map_err(|err| {
+            log::warn!("Failed to encode a packet for {:?}: {:?}", self.addr, err);
+            ActorError::BadRequest
+        })?;
+        self.queue_outgoing_bytes(cipherdata.as_slice());
         Ok(())
     }
 
@@ -195,18 +360,7 @@ impl ConnectionData {
 
     #[MojAuthProps]
     pub fn auth_props(&self) -> ActorResult<Vec<ProfileProperty>> {
-        Ok(self
-            .mojauth
-            .as_ref()
-            .ok_or(ActorError::ActorIsNotLoaded)?
-            .props
-            .iter()
-            .map(|x| ProfileProperty {
-                name: x.name.clone(),
-                value: x.value.clone(),
-                sig: Some(x.sig.clone()),
-            })
-            .collect::<Vec<_>>())
+        Ok(self.profile_properties.clone())
     }
 
     #[ChangeDimension]
@@ -372,6 +526,59 @@ impl ConnectionData {
         Ok(())
     }
 
+    #[HasPermissionInternal]
+    pub(crate) fn has_permission_internal(&mut self, permission: String) -> ActorResult<bool> {
+        Ok(self.check_permission_local(&permission))
+    }
+
+    #[GetOpLevel]
+    pub fn op_level(&mut self) -> ActorResult<u8> {
+        let uuid = self.get(PlayerComponents::UUID)?;
+        Ok(self.permissions.player(uuid).op_level)
+    }
+
+    #[SetOpLevel]
+    #[doc = "Sets the player's op level, from 0 to 4, and saves it to the server's permission storage."]
+    pub fn set_op_level(&mut self, level: u8) -> ActorResult<()> {
+        let uuid = self.get(PlayerComponents::UUID)?;
+        let mut permissions = self.permissions.player(uuid);
+        permissions.op_level = level.min(MAX_OP_LEVEL);
+        self.permissions.set_player(uuid, permissions);
+        self.update_permissions()
+    }
+
+    #[UpdatePermissions]
+    #[doc = "Resends the player's op level and the commands they can use. Call this after changing their permissions."]
+    pub fn update_permissions(&mut self) -> ActorResult<()> {
+        let uuid = self.get(PlayerComponents::UUID)?;
+        let level = self.permissions.player(uuid).op_level;
+        self.send_op_level(level);
+        self.send_command_tree()
+    }
+
+    #[SendCommandTree]
+    #[doc = "Sends every registered command the player can use, so their client can complete and validate them."]
+    pub fn send_command_tree(&mut self) -> ActorResult<()> {
+        let packet = self
+            .commands
+            .command_packet(&|permission| self.check_permission_local(permission));
+        self.write_packet(packet);
+        Ok(())
+    }
+
+    #[SendCommandSuggestions]
+    pub(crate) fn send_command_suggestions(
+        &mut self,
+        id: i32,
+        text: String,
+        start: usize,
+        length: usize,
+        matches: Vec<String>,
+    ) -> ActorResult<()> {
+        self.write_packet(suggestions_packet(id, &text, start, length, matches));
+        Ok(())
+    }
+
     #[SendActionBar]
     pub fn send_action_bar(&mut self, message: Text) -> ActorResult<()> {
         self.write_packet(SystemChatS2CPlayPacket {
@@ -541,4 +748,10 @@ impl ConnectionWithSignal {
     pub fn lower(&self) -> Player {
         self.player.clone()
     }
+
+    /// Whether the connection's thread is still running. A thread that panicked never sends
+    /// its stop signal, so a dropped signal also counts as stopped.
+    pub(crate) fn is_alive(&self) -> bool {
+        matches!(self._signal.try_recv(), Err(TryRecvError::Empty))
+    }
 }
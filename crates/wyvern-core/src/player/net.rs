@@ -1,39 +1,52 @@
 use std::{
-    collections::VecDeque,
+    collections::{HashMap, VecDeque},
     fmt::Debug,
-    io::{ErrorKind, Read, Write},
-    net::{IpAddr, TcpStream},
+    io::{ErrorKind, Read},
+    net::{IpAddr, Shutdown},
     sync::{Arc, Mutex},
-    time::{Duration, Instant},
+    time::Duration,
 };
 
 use crate::{
     actors::{Actor, ActorResult},
+    events::{CancellableEvent, Event, PendingEvent},
+    permissions::MemoryPermissionStorage,
     runtime::Runtime,
 };
-use flume::{Receiver, Sender};
+use flume::{Receiver, Selector, Sender};
+use mio::net::TcpStream;
 use voxidian_protocol::{
     packet::{
-        DecodeError, PrefixedPacketDecode, Stage, c2s::handshake::C2SHandshakePackets,
-        processing::PacketProcessing, s2c::play::KeepAliveS2CPlayPacket,
+        PrefixedPacketDecode, Stage, c2s::handshake::C2SHandshakePackets,
+        processing::PacketProcessing,
     },
     value::Uuid,
 };
 use wyvern_actors::ActorError;
 use wyvern_components::{DataComponentHolder, DataComponentMap};
-use wyvern_datatypes::gamemode::Gamemode;
+use wyvern_datatypes::{gamemode::Gamemode, text::Text};
 
-use crate::{player::PlayerMessage, server::Server};
+use crate::{
+    player::PlayerMessage,
+    server::{ForwardingMode, NetworkReactor, Server, parse_bungeecord_host},
+};
 
-use super::{ConnectionData, ConnectionWithSignal, Player, PlayerComponents, data::PlayerData};
+use super::{
+    Compression, ConnectionData, ConnectionWithSignal, DEFAULT_KEEP_ALIVE_TIMEOUT,
+    DEFAULT_MAX_QUEUED_BYTES, InboundPacket, LoginState, MalformedPacket, OutboundBuffer,
+    PacketDecision, Player, PlayerComponents, data::PlayerData, next_packet,
+};
 
 pub struct ConnectionStoppedSignal;
 
 impl ConnectionData {
+    /// Starts driving a new connection on its own thread, which sleeps until the reactor
+    /// reports activity on the socket or the player's actor receives a message.
     pub fn connection_channel(
         stream: TcpStream,
         addr: IpAddr,
         server: Server,
+        reactor: Arc<NetworkReactor>,
         stage: Arc<Mutex<Stage>>,
     ) -> ConnectionWithSignal {
         let (signal_tx, signal_rx) = flume::bounded(1);
@@ -43,7 +56,9 @@ impl ConnectionData {
         let data_tx2 = data_tx.clone();
         Runtime::spawn_actor(
             move || {
-                ConnectionData::new_conn(stream, addr, data_tx2, data_rx, signal_tx, server, stage2)
+                ConnectionData::new_conn(
+                    stream, addr, data_tx2, data_rx, signal_tx, server, reactor, stage2,
+                )
             },
             "PlayerThread",
         );
@@ -58,21 +73,61 @@ impl ConnectionData {
     }
 
     #[allow(clippy::too_many_arguments)]
-    pub fn new_conn(
+    pub(crate) fn new_conn(
         stream: TcpStream,
         addr: IpAddr,
         sender: Sender<PlayerMessage>,
         receiver: Receiver<PlayerMessage>,
         signal: Sender<ConnectionStoppedSignal>,
         server: Server,
+        reactor: Arc<NetworkReactor>,
         stage: Arc<Mutex<Stage>>,
     ) {
-        stream.set_nonblocking(true).unwrap();
-        let conn = ConnectionData {
+        let signal_copy = signal.clone();
+        match ConnectionData::new(
+            stream, addr, sender, receiver, signal, server, reactor, stage,
+        ) {
+            Ok(conn) => conn.event_loop(),
+            Err(err) => {
+                log::error!("Failed to register connection {:?}: {:?}", addr, err);
+                let _ = signal_copy.send(ConnectionStoppedSignal);
+            }
+        }
+    }
+
+    /// Sets up a connection's state and registers its socket with the reactor, without
+    /// starting to drive it.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
+        mut stream: TcpStream,
+        addr: IpAddr,
+        sender: Sender<PlayerMessage>,
+        receiver: Receiver<PlayerMessage>,
+        signal: Sender<ConnectionStoppedSignal>,
+        server: Server,
+        reactor: Arc<NetworkReactor>,
+        stage: Arc<Mutex<Stage>>,
+    ) -> std::io::Result<ConnectionData> {
+        let (reactor_token, wake) = reactor.register(&mut stream)?;
+        let packet_listeners = server.packet_listeners().unwrap_or_default();
+        let commands = server.commands().unwrap_or_default();
+        let permissions = server
+            .permission_storage()
+            .unwrap_or_else(|_| Arc::new(MemoryPermissionStorage::default()));
+        let keep_alive_timeout = server
+            .keep_alive_timeout()
+            .unwrap_or(DEFAULT_KEEP_ALIVE_TIMEOUT);
+        let max_queued_bytes = server
+            .max_queued_bytes()
+            .unwrap_or(DEFAULT_MAX_QUEUED_BYTES);
+        Ok(ConnectionData {
             stream,
+            reactor,
+            reactor_token,
+            wake,
             addr,
             received_bytes: VecDeque::new(),
-            bytes_to_send: Vec::new(),
+            outbound: OutboundBuffer::new(max_queued_bytes),
             packet_processing: PacketProcessing::NONE,
             receiver,
             sender,
@@ -80,65 +135,104 @@ impl ConnectionData {
             stage,
             connected_server: server,
             associated_data: PlayerData::default(),
+            login: LoginState::AwaitingHello,
             mojauth: None,
+            forwarded: None,
+            velocity_query_id: None,
+            profile_properties: Vec::new(),
+            packet_listeners,
+            commands,
+            permissions,
+            compression: Compression::Disabled,
+            keep_alive_timeout,
+            pending_cookies: HashMap::new(),
+            closing: false,
             components: DataComponentMap::new()
                 .with(PlayerComponents::GAMEMODE, Gamemode::Survival),
             last_saved_components: DataComponentMap::new(),
-        };
-
-        conn.event_loop();
+        })
     }
 
     pub fn event_loop(mut self) {
         loop {
             let result = self.handle_incoming_bytes();
-            if result.is_err() {
+            if result.is_err() || self.closing {
                 log::info!("A player has disconnected. Stopping their connection data...");
 
                 if let Some(dim) = &self.associated_data.dimension {
                     let _ = dim
                         .remove_entity(self.get(PlayerComponents::UUID).unwrap_or(Uuid::new_v4()));
                 }
-                self.signal.send(ConnectionStoppedSignal).unwrap();
+                self.write_outgoing_packets();
+                let _ = self.stream.shutdown(Shutdown::Both);
+                self.reactor
+                    .deregister(&mut self.stream, self.reactor_token);
+                let _ = self.signal.send(ConnectionStoppedSignal);
                 drop(self);
                 return;
             }
             self.handle_messages();
-            let _ = self.read_incoming_packets();
+            self.process_received_packets();
+            self.write_outgoing_packets();
+
+            let _ = self.tick_keep_alive();
+            self.expire_cookie_requests();
             self.write_outgoing_packets();
 
-            let now = Instant::now();
-            if now > self.associated_data.last_sent_keep_alive + Duration::from_secs(5)
-                && *self.stage.lock().unwrap() == Stage::Play
-            {
-                self.write_packet(KeepAliveS2CPlayPacket(10));
-                self.associated_data.last_sent_keep_alive = Instant::now();
+            self.wait_for_activity();
+        }
+    }
+
+    /// Sleeps until the socket has new readiness events, a message arrives,
+    /// or the keep alive or a cookie request needs attention.
+    fn wait_for_activity(&mut self) {
+        let timeout = self
+            .until_keep_alive()
+            .min(self.until_cookie_timeout().unwrap_or(Duration::MAX));
+        let message = Selector::new()
+            .recv(&self.receiver, |message| message.ok())
+            .recv(&self.wake, |_| None)
+            .wait_timeout(timeout);
+        if let Ok(Some(message)) = message {
+            self.handle_message(message);
+        }
+    }
+
+    /// Handles every complete packet that has been received so far.
+    pub(crate) fn process_received_packets(&mut self) {
+        while !self.closing {
+            let before = self.received_bytes.len();
+            let _ = self.read_incoming_packets();
+            if self.received_bytes.len() == before {
+                break;
             }
         }
     }
 
     pub fn handle_incoming_bytes(&mut self) -> Result<(), ()> {
-        let mut buf = [0; 512];
+        let mut buf = [0; 4096];
 
-        match self.stream.read(&mut buf) {
-            Ok(bytes_read) => {
-                if bytes_read == 0 {
-                    return Err(());
-                }
-                for byte in &buf[0..bytes_read] {
-                    let byte = self
-                        .packet_processing
-                        .secret_cipher
-                        .decrypt_u8(*byte)
-                        .unwrap();
-                    self.received_bytes.push_back(byte);
+        // Readiness events are edge-triggered, so the socket must be drained completely.
+        loop {
+            match self.stream.read(&mut buf) {
+                Ok(bytes_read) => {
+                    if bytes_read == 0 {
+                        return Err(());
+                    }
+                    for byte in &buf[0..bytes_read] {
+                        let Ok(byte) = self.packet_processing.secret_cipher.decrypt_u8(*byte)
+                        else {
+                            log::warn!("Failed to decrypt data from {:?}", self.addr);
+                            return Err(());
+                        };
+                        self.received_bytes.push_back(byte);
+                    }
                 }
-            }
-            Err(e) if e.kind() == ErrorKind::WouldBlock => {}
-            Err(_) => return Err(()),
-        };
-
-        Ok(())
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(_) => return Err(()),
+            };
+        }
     }
 
     pub fn read_incoming_packets(&mut self) -> ActorResult<()> {
@@ -147,14 +241,33 @@ impl ConnectionData {
             Stage::Handshake => {
                 self.read_packets(|packet: C2SHandshakePackets, this: &mut Self| {
                     let C2SHandshakePackets::Intention(packet) = packet;
-                    *this.stage.lock().unwrap() = packet.intended_stage.into_stage();
+                    this.associated_data.protocol_version = packet.protocol_version.as_i32();
+                    if this.connected_server.forwarding_mode()? == ForwardingMode::BungeeCord {
+                        this.forwarded = parse_bungeecord_host(&packet.server_address);
+                    }
+                    let stage = packet.intended_stage.into_stage();
+                    if stage != Stage::Transfer {
+                        *this.stage.lock().unwrap() = stage;
+                        return Ok(());
+                    }
+
+                    // Transferred players log in like any other player.
+                    *this.stage.lock().unwrap() = Stage::Login;
+                    if !this.connected_server.accepts_transfers()? {
+                        this.disconnect_internal(Text::literal(
+                            "This server does not accept transfers.",
+                        ))?;
+                        this.closing = true;
+                        return Ok(());
+                    }
+                    this.associated_data.transferred = true;
                     Ok(())
                 })?;
             }
             Stage::Status => {
                 self.status_stage()?;
             }
-            Stage::Login => {
+            Stage::Login | Stage::Transfer => {
                 self.login_stage()?;
             }
             Stage::Config => {
@@ -163,78 +276,104 @@ impl ConnectionData {
             Stage::Play => {
                 self.play_phase()?;
             }
-            Stage::Transfer => todo!("doesn't exist, this needs to be removed D:"),
         }
         Ok(())
     }
 
+    /// Writes queued packets until the socket stops accepting data. Whatever is left is
+    /// written once the reactor reports that the socket is writable again.
     pub fn write_outgoing_packets(&mut self) {
-        loop {
-            if self.bytes_to_send.is_empty() {
-                break;
-            }
+        if self.outbound.is_empty() {
+            return;
+        }
+        if let Err(e) = self.outbound.flush_to(&mut self.stream) {
+            log::debug!("Failed to write to {:?}: {:?}", self.addr, e);
+            self.outbound.clear();
+            self.closing = true;
+        }
+    }
+
+    /// Fires an event and blocks this connection until all of its handlers have finished.
+    pub(crate) fn fire_event_blocking<E: Event + Send + Sync + 'static>(
+        &mut self,
+        event: E,
+    ) -> ActorResult<Arc<E>> {
+        let pending = event.dispatch_tracked(self.connected_server.event_bus()?);
+        self.wait_for_handlers(&pending);
+        Ok(pending.into_event())
+    }
+
+    /// Fires a cancellable event and blocks this connection until all of its handlers have
+    /// finished, returning whether any of them cancelled it.
+    pub(crate) fn fire_cancellable_event<E: Event + CancellableEvent + Send + Sync + 'static>(
+        &mut self,
+        event: E,
+    ) -> ActorResult<bool> {
+        let pending = event.dispatch_tracked(self.connected_server.event_bus()?);
+        self.wait_for_handlers(&pending);
+        Ok(pending.event().is_cancelled())
+    }
+
+    /// Sleeps until the event's handlers finish, handling messages in the meantime. Packets
+    /// are left for after, so the packet that fired the event is fully handled before the
+    /// next one.
+    fn wait_for_handlers<E>(&mut self, pending: &PendingEvent<E>) {
+        while !pending.is_finished() {
+            self.expire_cookie_requests();
+            self.write_outgoing_packets();
 
-            match self.stream.write_all(&self.bytes_to_send) {
-                Ok(()) => {}
-                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
-                Err(e) if e.kind() == ErrorKind::BrokenPipe => {}
-                Err(e) => panic!("{:?}", e),
+            let selector = Selector::new()
+                .recv(pending.finished(), |_| None)
+                .recv(&self.receiver, |message| message.ok());
+            let message = match self.until_cookie_timeout() {
+                Some(timeout) => selector.wait_timeout(timeout).ok().flatten(),
+                None => selector.wait(),
+            };
+            if let Some(message) = message {
+                self.handle_message(message);
             }
-            self.bytes_to_send.clear();
         }
     }
 
     pub fn read_packets<
-        T: PrefixedPacketDecode + Debug,
+        T: PrefixedPacketDecode + InboundPacket + Debug,
         F: FnOnce(T, &mut Self) -> ActorResult<()>,
     >(
         &mut self,
         f: F,
     ) -> ActorResult<()> {
-        match self
-            .packet_processing
-            .decode_from_raw_queue(self.received_bytes.iter().copied())
-        {
-            Ok((mut buf, consumed)) => {
-                if consumed == 0 {
+        match next_packet::<T>(
+            &mut self.packet_processing,
+            self.compression,
+            &mut self.received_bytes,
+        ) {
+            Ok(None) => Ok(()),
+            Ok(Some(mut packet)) => {
+                if self.intercept_incoming(&mut packet) == PacketDecision::Cancel {
                     return Ok(());
                 }
-
-                let byte_cache = self.received_bytes.clone();
-                let mut rv = Vec::with_capacity(consumed);
-                for _ in 0..consumed {
-                    rv.push(self.received_bytes.pop_front().unwrap());
-                }
-
-                let buf_copy = buf.clone();
-                match T::decode_prefixed(&mut buf) {
-                    Ok(packet) => {
-                        f(packet, self)?;
-                        Ok(())
-                    }
-                    Err(DecodeError::EndOfBuffer) => {
-                        log::error!(
-                            "The server has encountered a packet decoding error!
-
-                        Buffer received: {:?}
-                        Buffer after receiving: {:?}
-                        Received bytes before consuming: {:?}
-                        Bytes left to receive: {:?}
-                        Bytes consumed: {:?}
-                        ",
-                            buf_copy.iter().collect::<Vec<u8>>(),
-                            buf.iter().collect::<Vec<u8>>(),
-                            byte_cache,
-                            self.received_bytes,
-                            rv
-                        );
-                        Ok(())
-                    }
-                    Err(_e) => Err(ActorError::BadRequest),
-                }
+                f(packet, self)
+            }
+            Err(MalformedPacket::Truncated(length)) => {
+                // Some packets are not fully supported by the protocol library yet,
+                // so these are skipped instead of disconnecting the player.
+                log::debug!(
+                    "Ignoring a {} packet of {} bytes from {:?} that could not be fully decoded",
+                    std::any::type_name::<T>(),
+                    length,
+                    self.addr
+                );
+                Ok(())
+            }
+            Err(err) => {
+                log::warn!(
+                    "Received a malformed packet from {:?}, disconnecting them: {:?}",
+                    self.addr,
+                    err
+                );
+                self.kick(Text::literal("Received a malformed packet"))?;
+                Err(ActorError::BadRequest)
             }
-            Err(DecodeError::EndOfBuffer) => Ok(()),
-            Err(_e) => Err(ActorError::BadRequest),
         }
     }
 }
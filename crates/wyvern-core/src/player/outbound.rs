@@ -0,0 +1,170 @@
+use std::io::{ErrorKind, Write};
+
+use super::ConnectionData;
+
+/// How many bytes may be queued for a player by default before they are disconnected.
+pub(crate) const DEFAULT_MAX_QUEUED_BYTES: usize = 16 * 1024 * 1024;
+/// Once this many bytes are queued, they are flushed right away instead of waiting for the
+/// connection's next loop.
+const FLUSH_THRESHOLD: usize = 64 * 1024;
+
+/// Bytes waiting to be written to a connection's socket. Packets are appended as they are
+/// sent and written together, so many small packets only need a single syscall.
+#[derive(Debug)]
+pub(crate) struct OutboundBuffer {
+    bytes: Vec<u8>,
+    written: usize,
+    max_queued: usize,
+}
+
+impl OutboundBuffer {
+    pub(crate) fn new(max_queued: usize) -> OutboundBuffer {
+        OutboundBuffer {
+            bytes: Vec::new(),
+            written: 0,
+            max_queued,
+        }
+    }
+
+    pub(crate) fn queued(&self) -> usize {
+        self.bytes.len() - self.written
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.queued() == 0
+    }
+
+    pub(crate) fn is_over_limit(&self) -> bool {
+        self.queued() > self.max_queued
+    }
+
+    pub(crate) fn push(&mut self, data: &[u8]) {
+        self.bytes.extend_from_slice(data);
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.bytes.clear();
+        self.written = 0;
+    }
+
+    /// Writes as much as the writer accepts without blocking. Anything left over stays
+    /// queued for the next flush.
+    pub(crate) fn flush_to(&mut self, writer: &mut impl Write) -> std::io::Result<()> {
+        while self.written < self.bytes.len() {
+            match writer.write(&self.bytes[self.written..]) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(n) => self.written += n,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        if self.written == self.bytes.len() {
+            self.clear();
+        } else if self.written > self.bytes.len() / 2 {
+            // Only shift the remaining bytes forward once most of the buffer has been written,
+            // so a slow socket does not cause a copy on every flush.
+            self.bytes.drain(..self.written);
+            self.written = 0;
+        }
+        Ok(())
+    }
+}
+
+impl ConnectionData {
+    pub(crate) fn queue_outgoing_bytes(&mut self, data: &[u8]) {
+        self.outbound.push(data);
+        if self.outbound.queued() >= FLUSH_THRESHOLD || self.outbound.is_over_limit() {
+            self.write_outgoing_packets();
+        }
+        if self.outbound.is_over_limit() {
+            log::warn!(
+                "{:?} has fallen too far behind on packets ({} bytes queued), disconnecting them",
+                self.addr,
+                self.outbound.queued()
+            );
+            self.outbound.clear();
+            self.closing = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Write};
+
+    use super::OutboundBuffer;
+
+    /// Accepts a limited number of bytes, then reports that it would block.
+    struct SlowWriter {
+        written: Vec<u8>,
+        capacity: usize,
+        calls: usize,
+    }
+
+    impl Write for SlowWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.calls += 1;
+            let n = buf.len().min(self.capacity - self.written.len());
+            if n == 0 {
+                return Err(ErrorKind::WouldBlock.into());
+            }
+            self.written.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn partial_writes_are_kept() {
+        let mut buffer = OutboundBuffer::new(1024);
+        let mut writer = SlowWriter {
+            written: Vec::new(),
+            capacity: 3,
+            calls: 0,
+        };
+        buffer.push(&[1, 2, 3, 4, 5]);
+        buffer.flush_to(&mut writer).unwrap();
+        assert_eq!(buffer.queued(), 2);
+
+        writer.capacity = 10;
+        buffer.push(&[6]);
+        buffer.flush_to(&mut writer).unwrap();
+        assert!(buffer.is_empty());
+        assert_eq!(writer.written, [1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn small_packets_are_batched() {
+        let mut buffer = OutboundBuffer::new(1024);
+        let mut writer = SlowWriter {
+            written: Vec::new(),
+            capacity: 1024,
+            calls: 0,
+        };
+        for i in 0..100 {
+            buffer.push(&[i]);
+        }
+        buffer.flush_to(&mut writer).unwrap();
+        assert_eq!(writer.calls, 1);
+        assert_eq!(writer.written.len(), 100);
+    }
+
+    #[test]
+    fn limit_counts_unwritten_bytes() {
+        let mut buffer = OutboundBuffer::new(4);
+        buffer.push(&[0; 5]);
+        assert!(buffer.is_over_limit());
+        let mut writer = SlowWriter {
+            written: Vec::new(),
+            capacity: 2,
+            calls: 0,
+        };
+        buffer.flush_to(&mut writer).unwrap();
+        assert!(!buffer.is_over_limit());
+    }
+}
@@ -0,0 +1,37 @@
+use voxidian_protocol::packet::s2c::play::EntityEventS2CPlayPacket;
+use wyvern_components::DataComponentHolder;
+
+use crate::{
+    actors::ActorResult,
+    permissions::{MAX_OP_LEVEL, check_permission},
+};
+
+use super::{ConnectionData, Player, PlayerComponents};
+
+/// Entity events 24 to 28 set the player's op level from 0 to 4.
+const OP_LEVEL_EVENT_BASE: u8 = 24;
+
+impl Player {
+    /// Checks whether the player has a permission node, like `wyvern.build`.
+    pub fn has_permission(&self, permission: &str) -> ActorResult<bool> {
+        self.has_permission_internal(permission.to_string())
+    }
+}
+
+impl ConnectionData {
+    /// Checks a permission without going through the actor, for use on the
+    /// connection's own thread.
+    pub(crate) fn check_permission_local(&self, permission: &str) -> bool {
+        match self.get(PlayerComponents::UUID) {
+            Ok(uuid) => check_permission(self.permissions.as_ref(), uuid, permission),
+            Err(_) => false,
+        }
+    }
+
+    pub(crate) fn send_op_level(&mut self, level: u8) {
+        self.write_packet(EntityEventS2CPlayPacket {
+            entity: self.associated_data.entity_id,
+            event: OP_LEVEL_EVENT_BASE + level.min(MAX_OP_LEVEL),
+        });
+    }
+}
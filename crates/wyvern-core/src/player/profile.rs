@@ -0,0 +1,53 @@
+use voxidian_protocol::value::{ProfileProperty, Uuid};
+
+use crate::entities::PlayerSkinData;
+
+/// Supplies player profiles when Mojang authentication is disabled.
+pub trait ProfileResolver: Send + Sync {
+    /// Returns the profile to use for the given username, or `None` to fall back to the
+    /// offline-mode profile.
+    fn resolve(&self, username: &str) -> Option<ResolvedProfile>;
+}
+
+#[derive(Debug, Clone)]
+pub struct ResolvedProfile {
+    pub uuid: Uuid,
+    pub skin: Option<PlayerSkinData>,
+}
+
+impl ResolvedProfile {
+    pub(crate) fn properties(&self) -> Vec<ProfileProperty> {
+        self.skin
+            .iter()
+            .map(|skin| ProfileProperty {
+                name: "textures".to_string(),
+                value: skin.texture.clone(),
+                sig: Some(skin.signature.clone()),
+            })
+            .collect()
+    }
+}
+
+/// Returns the UUID vanilla servers assign to a player in offline mode, which is a version 3
+/// UUID of `OfflinePlayer:<username>`.
+pub fn offline_uuid(username: &str) -> Uuid {
+    let mut bytes = md5::compute(format!("OfflinePlayer:{username}")).0;
+    bytes[6] = (bytes[6] & 0x0f) | 0x30;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    Uuid::from_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use voxidian_protocol::value::Uuid;
+
+    use super::offline_uuid;
+
+    #[test]
+    fn vanilla_offline_uuid() {
+        assert_eq!(
+            offline_uuid("Notch"),
+            Uuid::parse_str("b50ad385-829d-3141-a216-7e7d7539ba7f").unwrap()
+        );
+    }
+}
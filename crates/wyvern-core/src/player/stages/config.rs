@@ -10,6 +10,7 @@ use voxidian_protocol::{
     registry::RegEntry,
     value::{PaintingVariant as PtcPaintingVariant, VarInt, WolfVariant as PtcWolfVariant},
 };
+use wyvern_datatypes::text::Text;
 use wyvern_values::Id;
 
 use crate::{
@@ -25,7 +26,12 @@ impl ConnectionData {
             log::debug!("Packet: {:?}", packet);
             {
                 match packet {
-                    C2SConfigPackets::CustomPayload(_packet) => {}
+                    C2SConfigPackets::CustomPayload(packet) => {
+                        this.receive_plugin_message(
+                            packet.channel.into(),
+                            packet.data.as_slice().to_vec(),
+                        )?;
+                    }
                     C2SConfigPackets::FinishConfiguration(_packet) => {
                         *this.stage.lock().unwrap() = Stage::Play;
                         this.associated_data.entity_id = this.connected_server.new_entity_id()?;
@@ -74,6 +80,7 @@ impl ConnectionData {
                                 sea_level: VarInt::from(64),
                                 enforce_chat_reports: false,
                             })?;
+                            p.update_permissions()?;
 
                             p.write_packet(PlayerPositionS2CPlayPacket {
                                 teleport_id: VarInt::from(0),
@@ -104,20 +111,31 @@ impl ConnectionData {
                         ResourcePackStatus::SuccessfullyDownloaded => {
                             this.write_packet(FinishConfigurationS2CConfigPacket);
                         }
-                        ResourcePackStatus::Declined => todo!(),
-                        ResourcePackStatus::FailedDownload => todo!(),
+                        ResourcePackStatus::Declined
+                        | ResourcePackStatus::FailedDownload
+                        | ResourcePackStatus::InvalidURL => {
+                            this.kick(Text::literal(
+                                "This server requires you to use its resource pack.",
+                            ))?;
+                        }
                         ResourcePackStatus::Accepted => {}
                         ResourcePackStatus::Downloaded => {}
-                        ResourcePackStatus::InvalidURL => todo!(),
                         ResourcePackStatus::FailedReload => {}
-                        ResourcePackStatus::Discarded => todo!(),
+                        ResourcePackStatus::Discarded => {}
                     },
-                    C2SConfigPackets::CookieResponse(_packet) => todo!(),
-                    C2SConfigPackets::Pong(_packet) => todo!(),
+                    C2SConfigPackets::CookieResponse(packet) => {
+                        this.receive_cookie(
+                            packet.key.into(),
+                            packet.payload.map(|payload| payload.as_slice().to_vec()),
+                        );
+                    }
+                    C2SConfigPackets::Pong(_packet) => {}
                     C2SConfigPackets::ClientInformation(packet) => {
                         this.associated_data.render_distance = packet.info.view_distance as i32;
                     }
-                    C2SConfigPackets::KeepAlive(_packet) => todo!(),
+                    C2SConfigPackets::KeepAlive(packet) => {
+                        this.receive_keep_alive(packet.0)?;
+                    }
                     C2SConfigPackets::SelectKnownPacks(_packet) => {
                         this.write_packet(
                             this.connected_server
@@ -1,24 +1,36 @@
 use voxidian_protocol::{
-    mojang::auth_verify::{MojAuth, MojAuthError},
+    MINECRAFT_VERSION,
+    mojang::auth_verify::MojAuthError,
     packet::{
         Stage,
         c2s::login::C2SLoginPackets,
-        processing::{CompressionMode, SecretCipher, generate_key_pair},
+        processing::{SecretCipher, generate_key_pair},
         s2c::{
             config::{KnownPack, SelectKnownPacksS2CConfigPacket},
             login::{
-                HelloS2CLoginPacket, LoginCompressionS2CLoginPacket, LoginFinishedS2CLoginPacket,
+                CustomQueryS2CLoginPacket, HelloS2CLoginPacket, LoginCompressionS2CLoginPacket,
+                LoginFinishedS2CLoginPacket,
             },
         },
     },
-    value::{LengthPrefixHashMap, VarInt},
+    value::{Identifier, LengthPrefixHashMap, VarInt},
 };
 use wyvern_components::DataComponentHolder;
+use wyvern_datatypes::text::Text;
+use wyvern_values::cell::Token;
 
 use crate::{
     actors::{ActorError, ActorResult},
-    player::{ConnectionData, MojauthData, PlayerComponents},
-    server::Server,
+    events::{PlayerPreLoginEvent, PreLoginResult},
+    player::{
+        AuthenticatedProfile, ConnectionData, LoginState, MojauthData, PlayerComponents,
+        auth_error_reason, has_joined, is_valid_username, offline_uuid,
+    },
+    runtime::Runtime,
+    server::{
+        ForwardedPlayer, ForwardingMode, Server, VELOCITY_CHANNEL,
+        VELOCITY_MODERN_FORWARDING_VERSION, parse_velocity_response,
+    },
 };
 
 impl ConnectionData {
@@ -26,157 +38,452 @@ impl ConnectionData {
         self.read_packets(|packet: C2SLoginPackets, this: &mut Self| {
             log::debug!("Packet: {:?}", packet);
             match packet {
-                C2SLoginPackets::CustomQueryAnswer(_packet) => todo!(),
+                C2SLoginPackets::CustomQueryAnswer(packet) => {
+                    let ForwardingMode::Velocity { secret } =
+                        this.connected_server.forwarding_mode()?
+                    else {
+                        return Ok(());
+                    };
+                    let LoginState::AwaitingForwarding(username) = &this.login else {
+                        return this.reject_login("Unexpected packet");
+                    };
+                    if this.velocity_query_id != Some(packet.transaction_id.as_i32()) {
+                        return Ok(());
+                    }
+                    let username = username.clone();
+                    this.velocity_query_id = None;
+
+                    let forwarded = packet
+                        .data
+                        .and_then(|data| parse_velocity_response(secret.as_bytes(), &data));
+                    let Some(forwarded) = forwarded else {
+                        log::warn!("Received invalid Velocity forwarding data from {:?}", this.addr);
+                        this.disconnect_internal(Text::literal(
+                            "Unable to verify player details, connect through the proxy.",
+                        ))?;
+                        return Err(ActorError::BadRequest);
+                    };
+                    this.apply_forwarded_player(forwarded, username)?;
+                    this.finish_login(true)?;
+                }
                 C2SLoginPackets::LoginAcknowledged(_packet) => {
+                    if this.login != LoginState::Finished {
+                        return this.reject_login("Unexpected packet");
+                    }
                     *this.stage.lock().unwrap() = Stage::Config;
                     this.write_packet(SelectKnownPacksS2CConfigPacket {
                         known_packs: vec![KnownPack {
                             namespace: "minecraft".to_string(),
                             id: "core".to_string(),
-                            version: "1.21.4".to_string(),
+                            version: MINECRAFT_VERSION.to_string(),
                         }]
                         .into(),
                     });
+                    this.send_server_channels()?;
                 }
                 C2SLoginPackets::Key(packet) => {
-                    let Ok(decrypted_verify_token) = this
-                        .mojauth
-                        .as_ref()
-                        .ok_or(ActorError::ActorIsNotLoaded)?
-                        .private_key
-                        .as_ref()
-                        .unwrap()
-                        .decrypt(packet.verify_token.as_slice())
-                    else {
-                        return Err(ActorError::ActorDoesNotExist);
+                    let LoginState::AwaitingKey(username) = &this.login else {
+                        return this.reject_login("Unexpected packet");
                     };
-                    if decrypted_verify_token
-                        != this
-                            .mojauth
-                            .as_ref()
-                            .ok_or(ActorError::ActorIsNotLoaded)?
-                            .verify_token
-                            .as_slice()
-                    {
-                        return Err(ActorError::ActorDoesNotExist);
-                    }
-
-                    let Ok(secret_key) = this
-                        .mojauth
-                        .as_ref()
-                        .ok_or(ActorError::ActorIsNotLoaded)?
-                        .private_key
-                        .as_ref()
-                        .unwrap()
-                        .decrypt(packet.secret_key.as_slice())
+                    let username = username.clone();
+                    let Some(MojauthData {
+                        private_key: Some(private_key),
+                        public_key: Some(public_key),
+                        verify_token,
+                    }) = this.mojauth.as_ref()
                     else {
-                        return Err(ActorError::ActorDoesNotExist);
+                        return this.reject_login("Unexpected encryption response");
+                    };
+                    let verified = private_key
+                        .decrypt(packet.verify_token.as_slice())
+                        .is_ok_and(|token| token == verify_token.as_slice());
+                    let secret_key = private_key.decrypt(packet.secret_key.as_slice());
+                    let public_key = public_key.der_bytes().to_vec();
+                    let (true, Ok(secret_key)) = (verified, secret_key) else {
+                        return this.reject_login("Invalid encryption response");
                     };
+                    this.mojauth = None;
+                    this.login = LoginState::Authenticating;
 
                     let secret_cipher = SecretCipher::from_key_bytes(&secret_key);
                     this.packet_processing.secret_cipher = secret_cipher;
 
-                    let mojauth = match MojAuth::start_blocking(
-                        None,
-                        this.get(PlayerComponents::USERNAME)?,
-                        "WyvernMC",
-                        this.packet_processing.secret_cipher.key().unwrap(),
-                        this.mojauth
-                            .as_ref()
-                            .ok_or(ActorError::ActorIsNotLoaded)?
-                            .public_key
-                            .as_ref()
-                            .unwrap(),
-                    ) {
-                        Ok(mojauth) => mojauth,
-                        Err(err) => {
-                            return Err(match err {
-                                MojAuthError::AuthServerDown => ActorError::ActorDoesNotExist,
-                                MojAuthError::InvalidData => ActorError::ActorDoesNotExist,
-                                MojAuthError::Unverified => ActorError::ActorDoesNotExist,
-                            });
-                        }
-                    };
-
-                    this.set(PlayerComponents::USERNAME, mojauth.name);
-                    this.set(PlayerComponents::UUID, mojauth.uuid);
-                    this.mojauth
-                        .as_mut()
-                        .ok_or(ActorError::ActorIsNotLoaded)?
-                        .props = mojauth.props;
-
-                    this.write_packet(LoginFinishedS2CLoginPacket {
-                        uuid: this.get(PlayerComponents::UUID)?,
-                        username: this.get(PlayerComponents::USERNAME)?,
-                        props: LengthPrefixHashMap::new(),
-                    });
+                    let shared_secret = secret_key.to_vec();
+                    let session_server = this.connected_server.session_server()?;
+                    let player = this.as_actor();
+                    Runtime::spawn_actor(
+                        move || {
+                            let result = has_joined(
+                                &session_server,
+                                &username,
+                                "WyvernMC",
+                                &shared_secret,
+                                &public_key,
+                            );
+                            let _ = player.complete_auth(result);
+                        },
+                        "AuthThread",
+                    );
                 }
                 C2SLoginPackets::Hello(packet) => {
-                    this.write_packet(LoginCompressionS2CLoginPacket {
-                        threshold: VarInt::from(128),
-                    });
-                    this.packet_processing.compression = CompressionMode::ZLib { threshold: 128 };
+                    if this.login != LoginState::AwaitingHello {
+                        return this.reject_login("Unexpected packet");
+                    }
 
-                    this.set(PlayerComponents::USERNAME, packet.username);
-                    this.set(PlayerComponents::UUID, packet.uuid);
+                    match this.connected_server.forwarding_mode()? {
+                        // Names forwarded by a proxy are checked once forwarding is applied.
+                        ForwardingMode::None => {
+                            if !is_valid_username(&packet.username) {
+                                return this.reject_username();
+                            }
+                        }
+                        ForwardingMode::Velocity { .. } => {
+                            let id = rand::random::<i32>() & i32::MAX;
+                            this.velocity_query_id = Some(id);
+                            this.login = LoginState::AwaitingForwarding(packet.username);
+                            this.write_packet(CustomQueryS2CLoginPacket {
+                                transaction_id: VarInt::from(id),
+                                channel: Identifier::new("velocity", "player_info"),
+                                data: vec![VELOCITY_MODERN_FORWARDING_VERSION].into(),
+                            });
+                            log::debug!("Sent {} query to {:?}", VELOCITY_CHANNEL, this.addr);
+                            return Ok(());
+                        }
+                        ForwardingMode::BungeeCord => {
+                            let Some(forwarded) = this.forwarded.take() else {
+                                this.disconnect_internal(Text::literal(
+                                    "If you wish to use IP forwarding, please enable it in your BungeeCord config as well!",
+                                ))?;
+                                return Err(ActorError::BadRequest);
+                            };
+                            this.apply_forwarded_player(forwarded, packet.username)?;
+                            this.finish_login(true)?;
+                            return Ok(());
+                        }
+                    }
 
                     if Server::get()?.mojauth_enabled()? {
-                        this.mojauth = Some(MojauthData {
-                            private_key: None,
-                            verify_token: Vec::new(),
-                            public_key: None,
-                            props: Vec::new(),
-                        });
-
-                        let (private, public) = generate_key_pair::<1024>();
+                        let (private_key, public_key) = generate_key_pair::<1024>();
                         let verify_token =
                             std::array::from_fn::<_, 4, _>(|_| rand::random::<u8>()).to_vec();
 
-                        this.mojauth
-                            .as_mut()
-                            .ok_or(ActorError::ActorIsNotLoaded)?
-                            .private_key = Some(private);
-                        this.mojauth
-                            .as_mut()
-                            .ok_or(ActorError::ActorIsNotLoaded)?
-                            .public_key = Some(public);
-                        this.mojauth
-                            .as_mut()
-                            .ok_or(ActorError::ActorIsNotLoaded)?
-                            .verify_token = verify_token;
-
                         this.write_packet(HelloS2CLoginPacket {
                             server_id: "WyvernMC".to_string(),
-                            public_key: this
-                                .mojauth
-                                .as_ref()
-                                .ok_or(ActorError::ActorIsNotLoaded)?
-                                .public_key
-                                .as_ref()
-                                .unwrap()
-                                .der_bytes()
-                                .into(),
-                            verify_token: this
-                                .mojauth
-                                .as_ref()
-                                .ok_or(ActorError::ActorIsNotLoaded)?
-                                .verify_token
-                                .clone()
-                                .into(),
+                            public_key: public_key.der_bytes().into(),
+                            verify_token: verify_token.clone().into(),
                             should_auth: true,
                         });
-                    } else {
-                        this.write_packet(LoginFinishedS2CLoginPacket {
-                            uuid: this.get(PlayerComponents::UUID)?,
-                            username: this.get(PlayerComponents::USERNAME)?,
-                            props: LengthPrefixHashMap::new(),
+                        this.mojauth = Some(MojauthData {
+                            private_key: Some(private_key),
+                            verify_token,
+                            public_key: Some(public_key),
                         });
+                        this.login = LoginState::AwaitingKey(packet.username);
+                    } else {
+                        let username = packet.username;
+                        this.set(PlayerComponents::USERNAME, username.clone());
+                        let profile = this
+                            .connected_server
+                            .profile_resolver()?
+                            .and_then(|resolver| resolver.resolve(&username));
+                        match profile {
+                            Some(profile) => {
+                                this.set(PlayerComponents::UUID, profile.uuid);
+                                this.profile_properties = profile.properties();
+                            }
+                            None => {
+                                this.set(PlayerComponents::UUID, offline_uuid(&username));
+                            }
+                        }
+                        this.finish_login(false)?;
                     }
                 }
-                C2SLoginPackets::CookieResponse(_packet) => todo!(),
+                C2SLoginPackets::CookieResponse(packet) => {
+                    this.receive_cookie(
+                        packet.key.into(),
+                        packet.payload.map(|payload| payload.as_slice().to_vec()),
+                    );
+                }
             }
 
             Ok(())
         })
     }
+
+    fn reject_login(&mut self, reason: &str) -> ActorResult<()> {
+        log::warn!("Rejecting the login of {:?}: {}", self.addr, reason);
+        self.kick(Text::literal(reason))?;
+        Err(ActorError::BadRequest)
+    }
+
+    pub(crate) fn apply_auth_result(
+        &mut self,
+        result: Result<AuthenticatedProfile, MojAuthError>,
+    ) -> ActorResult<()> {
+        let profile = match result {
+            Ok(profile) => profile,
+            Err(err) => {
+                let reason = auth_error_reason(&err);
+                log::info!("Authentication of {:?} failed: {:?}", self.addr, reason);
+                self.disconnect_internal(reason)?;
+                return Err(ActorError::BadRequest);
+            }
+        };
+
+        self.set(PlayerComponents::USERNAME, profile.name);
+        self.set(PlayerComponents::UUID, profile.uuid);
+        self.profile_properties = profile.properties;
+        self.finish_login(true)
+    }
+
+    fn reject_username(&mut self) -> ActorResult<()> {
+        self.disconnect_internal(Text::literal("Invalid username!"))?;
+        Err(ActorError::BadRequest)
+    }
+
+    /// Takes the player's details from the proxy. BungeeCord does not forward the name, so
+    /// the one the client sent is used instead.
+    pub(crate) fn apply_forwarded_player(
+        &mut self,
+        forwarded: ForwardedPlayer,
+        username: String,
+    ) -> ActorResult<()> {
+        let username = forwarded.username.unwrap_or(username);
+        if !is_valid_username(&username) {
+            return self.reject_username();
+        }
+        self.addr = forwarded.addr;
+        self.set(PlayerComponents::UUID, forwarded.uuid);
+        self.set(PlayerComponents::USERNAME, username);
+        self.profile_properties = forwarded.properties;
+        Ok(())
+    }
+
+    pub(crate) fn finish_login(&mut self, authenticated: bool) -> ActorResult<()> {
+        self.login = LoginState::Finished;
+        let result = Token::new(PreLoginResult::Allowed);
+        let compression = Token::new(self.connected_server.compression()?);
+        self.fire_event_blocking(PlayerPreLoginEvent {
+            username: self.get(PlayerComponents::USERNAME)?,
+            uuid: self.get(PlayerComponents::UUID)?,
+            addr: self.addr,
+            authenticated,
+            transferred: self.associated_data.transferred,
+            result: result.clone(),
+            compression: compression.clone(),
+        })?;
+
+        if let PreLoginResult::Denied(reason) = result.get() {
+            log::info!(
+                "Login of {:?} was denied by a PlayerPreLoginEvent handler",
+                self.get(PlayerComponents::USERNAME)?
+            );
+            self.disconnect_internal(reason)?;
+            return Err(ActorError::BadRequest);
+        }
+
+        let compression = compression.get();
+        if let Some(threshold) = compression.threshold() {
+            self.write_packet(LoginCompressionS2CLoginPacket {
+                threshold: VarInt::from(threshold as i32),
+            });
+        }
+        self.compression = compression;
+
+        self.write_packet(LoginFinishedS2CLoginPacket {
+            uuid: self.get(PlayerComponents::UUID)?,
+            username: self.get(PlayerComponents::USERNAME)?,
+            props: LengthPrefixHashMap::new(),
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::{Ipv4Addr, SocketAddr, TcpStream},
+        sync::Arc,
+        thread::JoinHandle,
+        time::Duration,
+    };
+
+    use hmac::{Hmac, Mac};
+    use sha2::Sha256;
+    use voxidian_protocol::value::Uuid;
+    use wyvern_datatypes::text::Text;
+
+    use crate::{
+        events::ServerStartEvent,
+        player::Compression,
+        server::{ForwardingMode, Server, ServerBuilder},
+        varint::{PayloadReader, read_varint, write_string, write_varint},
+    };
+
+    const UUID: u128 = 0x1234;
+
+    /// Starts a server on a free port, returning where it listens and a handle to stop it.
+    fn start_server(
+        forwarding: ForwardingMode,
+    ) -> (SocketAddr, Server, JoinHandle<std::io::Result<()>>) {
+        let (started_tx, started) = flume::bounded(1);
+        let thread = std::thread::spawn(move || {
+            ServerBuilder::new()
+                .bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
+                .forwarding(forwarding)
+                .compression(Compression::Disabled)
+                .event(move |event: Arc<ServerStartEvent>| {
+                    let started_tx = started_tx.clone();
+                    async move {
+                        let addr = event.server.local_addrs()?[0];
+                        let _ = started_tx.send((addr, event.server.clone()));
+                        Ok(())
+                    }
+                })
+                .run()
+        });
+        let (addr, server) = started.recv_timeout(Duration::from_secs(30)).unwrap();
+        (addr, server, thread)
+    }
+
+    fn stop_server(server: Server, thread: JoinHandle<std::io::Result<()>>) {
+        server.shutdown(Text::literal("Test finished")).unwrap();
+        thread.join().unwrap().unwrap();
+    }
+
+    fn connect(addr: SocketAddr, host: &str) -> TcpStream {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        let mut intention = Vec::new();
+        write_varint(&mut intention, 770);
+        write_string(&mut intention, host);
+        intention.extend_from_slice(&25565u16.to_be_bytes());
+        write_varint(&mut intention, 2);
+        send(&mut stream, 0x00, &intention);
+        stream
+    }
+
+    fn send_hello(stream: &mut TcpStream, username: &str) {
+        let mut hello = Vec::new();
+        write_string(&mut hello, username);
+        hello.extend_from_slice(&0u128.to_be_bytes());
+        send(stream, 0x00, &hello);
+    }
+
+    fn send(stream: &mut TcpStream, id: i32, body: &[u8]) {
+        let mut packet = Vec::new();
+        write_varint(&mut packet, id);
+        packet.extend_from_slice(body);
+        let mut frame = Vec::new();
+        write_varint(&mut frame, packet.len() as i32);
+        frame.extend(packet);
+        stream.write_all(&frame).unwrap();
+    }
+
+    fn receive(stream: &mut TcpStream) -> (i32, Vec<u8>) {
+        let mut length = Vec::new();
+        loop {
+            let mut byte = [0];
+            stream.read_exact(&mut byte).unwrap();
+            length.push(byte[0]);
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+        let (length, _) = read_varint(length).unwrap();
+        let mut data = vec![0; length as usize];
+        stream.read_exact(&mut data).unwrap();
+        let (id, id_length) = read_varint(data.iter().copied()).unwrap();
+        (id, data[id_length..].to_vec())
+    }
+
+    /// Reads the `LoginFinished` packet, returning the UUID and name the server settled on.
+    fn receive_login_finished(stream: &mut TcpStream) -> (Uuid, String) {
+        let (id, body) = receive(stream);
+        assert_eq!(id, 0x02, "expected LoginFinished");
+        let mut reader = PayloadReader::new(&body);
+        let uuid = u128::from_be_bytes(reader.bytes(16).unwrap().try_into().unwrap());
+        (Uuid::from_u128(uuid), reader.string().unwrap())
+    }
+
+    fn velocity_response(secret: &[u8], username: &str) -> Vec<u8> {
+        let mut payload = Vec::new();
+        write_varint(&mut payload, 1);
+        write_string(&mut payload, "10.0.0.5");
+        payload.extend_from_slice(&UUID.to_be_bytes());
+        write_string(&mut payload, username);
+        write_varint(&mut payload, 0);
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret).unwrap();
+        mac.update(&payload);
+        let mut data = mac.finalize().into_bytes().to_vec();
+        data.extend(payload);
+        data
+    }
+
+    /// Logs in through a fake Velocity proxy, up to answering the forwarding query.
+    fn login_through_velocity(addr: SocketAddr, forwarded_name: &str) -> TcpStream {
+        let mut stream = connect(addr, "localhost");
+        send_hello(&mut stream, "Alex");
+
+        let (id, body) = receive(&mut stream);
+        assert_eq!(id, 0x04, "expected CustomQuery");
+        let mut reader = PayloadReader::new(&body);
+        let transaction_id = reader.varint().unwrap();
+        assert_eq!(reader.string().as_deref(), Some("velocity:player_info"));
+
+        let mut answer = Vec::new();
+        write_varint(&mut answer, transaction_id);
+        answer.push(1);
+        answer.extend(velocity_response(b"secret", forwarded_name));
+        send(&mut stream, 0x02, &answer);
+        stream
+    }
+
+    #[test]
+    fn velocity_login() {
+        let (addr, server, thread) = start_server(ForwardingMode::Velocity {
+            secret: "secret".to_string(),
+        });
+
+        let mut stream = login_through_velocity(addr, "Steve");
+        // The forwarded name replaces the one the client sent.
+        assert_eq!(
+            receive_login_finished(&mut stream),
+            (Uuid::from_u128(UUID), "Steve".to_string())
+        );
+        drop(stream);
+
+        let mut stream = login_through_velocity(addr, "not a name!");
+        let (id, _) = receive(&mut stream);
+        assert_eq!(id, 0x00, "expected LoginDisconnect");
+        drop(stream);
+
+        stop_server(server, thread);
+    }
+
+    #[test]
+    fn bungeecord_login() {
+        let (addr, server, thread) = start_server(ForwardingMode::BungeeCord);
+
+        let host = format!("localhost\0{}\0{}", "10.0.0.5", Uuid::from_u128(UUID));
+        let mut stream = connect(addr, &host);
+        send_hello(&mut stream, "Steve");
+        assert_eq!(
+            receive_login_finished(&mut stream),
+            (Uuid::from_u128(UUID), "Steve".to_string())
+        );
+        drop(stream);
+
+        let mut stream = connect(addr, &host);
+        send_hello(&mut stream, "not a name!");
+        let (id, _) = receive(&mut stream);
+        assert_eq!(id, 0x00, "expected LoginDisconnect");
+        drop(stream);
+
+        stop_server(server, thread);
+    }
 }
@@ -3,25 +3,27 @@ use voxidian_protocol::{
         c2s::play::{BlockFace, C2SPlayPackets, CommandAction, InteractAction, PlayerStatus},
         s2c::play::{
             AddEntityS2CPlayPacket, AnimateS2CPlayPacket, BlockChangedAckS2CPlayPacket,
-            ContainerSlotGroup, DisconnectS2CPlayPacket, EntityAnimation, GameEvent,
-            GameEventS2CPlayPacket, Hand, PlayerActionEntry, PlayerInfoUpdateS2CPlayPacket,
-            PongResponseS2CPlayPacket, RespawnDataKept, RespawnS2CPlayPacket, ScreenWindowKind,
-            SetEntityDataS2CPlayPacket,
+            BlockUpdateS2CPlayPacket, ContainerSlotGroup, DisconnectS2CPlayPacket, EntityAnimation,
+            GameEvent, GameEventS2CPlayPacket, Hand, PlayerActionEntry,
+            PlayerInfoUpdateS2CPlayPacket, PlayerRotationS2CPlayPacket, PongResponseS2CPlayPacket,
+            RespawnDataKept, RespawnS2CPlayPacket, ScreenWindowKind, SetEntityDataS2CPlayPacket,
         },
     },
     registry::RegEntry,
-    value::{Angle, ProfileProperty, Text as PtcText, TextComponent, VarInt},
+    value::{Angle, BlockPos, ProfileProperty, Text as PtcText, TextComponent, VarInt},
 };
+
 use wyvern_components::DataComponentHolder;
 use wyvern_datatypes::{gamemode::Gamemode, text::Text};
 
 use crate::{
     actors::{Actor, ActorError, ActorResult},
     blocks::BlockState,
+    commands::CommandSender,
     entities::EntityComponents,
     events::{
-        BreakBlockEvent, ChangeHeldSlotEvent, ChatMessageEvent, DropItemEvent, PlaceBlockEvent,
-        PlayerAttackEntityEvent, PlayerAttackPlayerEvent, PlayerCommandEvent, PlayerJoinEvent,
+        BreakBlockEvent, ChangeHeldSlotEvent, ChatMessageEvent, CommandEvent, DropItemEvent,
+        PlaceBlockEvent, PlayerAttackEntityEvent, PlayerAttackPlayerEvent, PlayerJoinEvent,
         PlayerLeftClickEvent, PlayerLoadEvent, PlayerMoveEvent, PlayerRespawnEvent,
         RightClickEvent, StartBreakBlockEvent, SwapHandsEvent,
     },
@@ -45,9 +47,40 @@ impl ConnectionData {
                 );
 
                 match packet {
+                    C2SPlayPackets::ChatCommand(packet)
+                        if this.commands.contains(&packet.command) =>
+                    {
+                        Runtime::spawn_task(this.commands.clone().dispatch(
+                            this.connected_server.clone(),
+                            CommandSender::Player(this.as_actor()),
+                            packet.command,
+                        ));
+                    }
+                    C2SPlayPackets::CommandSuggestion(packet) => {
+                        let commands = this.commands.clone();
+                        let server = this.connected_server.clone();
+                        let player = this.as_actor();
+                        Runtime::spawn_task(async move {
+                            // The client includes the leading slash, which suggestions must
+                            // account for.
+                            let text = packet.command;
+                            let input = text.strip_prefix('/').unwrap_or(&text);
+                            let offset = text.len() - input.len();
+                            let sender = CommandSender::Player(player.clone());
+                            let (start, matches) = commands.suggest(&server, &sender, input);
+                            player.send_command_suggestions(
+                                packet.id.as_i32(),
+                                text.clone(),
+                                start + offset,
+                                input.len() - start,
+                                matches,
+                            )
+                        });
+                    }
                     C2SPlayPackets::ChatCommand(packet) => {
-                        this.connected_server.spawn_event(PlayerCommandEvent {
-                            player: this.as_actor(),
+                        this.connected_server.spawn_event(CommandEvent {
+                            server: this.connected_server.clone(),
+                            sender: CommandSender::Player(this.as_actor()),
                             command: packet.command,
                         })?;
                     }
@@ -55,7 +88,6 @@ impl ConnectionData {
                         let block =
                             IVec3::new(packet.location.x, packet.location.y, packet.location.z);
 
-                        this.write_packet(BlockChangedAckS2CPlayPacket(packet.sequence));
                         match packet.status {
                             PlayerStatus::StartedDigging => {
                                 this.connected_server.spawn_event(StartBreakBlockEvent {
@@ -63,68 +95,17 @@ impl ConnectionData {
                                     position: block,
                                 })?;
                                 if this.get(PlayerComponents::GAMEMODE) == Ok(Gamemode::Creative) {
-                                    let old_block = this
-                                        .associated_data
-                                        .dimension
-                                        .as_ref()
-                                        .unwrap()
-                                        .get_block(block)?;
-
-                                    this.associated_data.dimension.as_ref().unwrap().set_block(
-                                        block,
-                                        BlockState::new(Id::constant("minecraft", "air")),
-                                    )?;
-                                    this.connected_server.spawn_event(BreakBlockEvent {
-                                        player: this.as_actor(),
-                                        position: block,
-                                        old_block,
-                                    })?;
+                                    this.break_block(block)?;
                                 }
                             }
                             PlayerStatus::CancelledDigging => {}
                             PlayerStatus::FinishedDigging => {
                                 if this.get(PlayerComponents::GAMEMODE) != Ok(Gamemode::Creative) {
-                                    let old_block = this
-                                        .associated_data
-                                        .dimension
-                                        .as_ref()
-                                        .unwrap()
-                                        .get_block(block)?;
-
-                                    this.associated_data.dimension.as_ref().unwrap().set_block(
-                                        block,
-                                        BlockState::new(Id::constant("minecraft", "air")),
-                                    )?;
-                                    this.connected_server.spawn_event(BreakBlockEvent {
-                                        player: this.as_actor(),
-                                        position: block,
-                                        old_block,
-                                    })?;
+                                    this.break_block(block)?;
                                 }
                             }
-                            PlayerStatus::DropItemStack => {
-                                let item =
-                                    this.get_inv_slot(this.associated_data.held_slot as usize)?;
-                                this.set_inv_slot(
-                                    this.associated_data.held_slot as usize,
-                                    ItemStack::air(),
-                                )?;
-                                this.connected_server.spawn_event(DropItemEvent {
-                                    player: this.as_actor(),
-                                    item,
-                                })?;
-                            }
-                            PlayerStatus::DropItem => {
-                                let item =
-                                    this.get_inv_slot(this.associated_data.held_slot as usize)?;
-                                this.set_inv_slot(
-                                    this.associated_data.held_slot as usize,
-                                    ItemStack::air(),
-                                )?;
-                                this.connected_server.spawn_event(DropItemEvent {
-                                    player: this.as_actor(),
-                                    item,
-                                })?;
+                            PlayerStatus::DropItemStack | PlayerStatus::DropItem => {
+                                this.drop_held_item()?;
                             }
                             PlayerStatus::FinishUsingItem => {}
                             PlayerStatus::SwapItems => {
@@ -133,6 +114,9 @@ impl ConnectionData {
                                 })?;
                             }
                         }
+                        // The client reverts every block it predicted when this arrives, unless
+                        // the server sent an update for it first.
+                        this.write_packet(BlockChangedAckS2CPlayPacket(packet.sequence));
                     }
                     C2SPlayPackets::AcceptTeleportation(packet) => {
                         if packet.teleport_id.as_i32() == 0 {
@@ -154,20 +138,8 @@ impl ConnectionData {
                         {
                             return Ok(());
                         }
-                        this.set(
-                            PlayerComponents::POSITION,
-                            DVec3::new(packet.x, packet.y, packet.z),
-                        );
-
-                        this.send_chunks()?;
-
-                        this.connected_server.spawn_event(PlayerMoveEvent {
-                            player: this.as_actor(),
-                            new_position: this.get(PlayerComponents::POSITION)?,
-                            new_direction: this.get(PlayerComponents::DIRECTION)?,
-                        })?;
-
-                        this.update_self_entity()?;
+                        let direction = this.get(PlayerComponents::DIRECTION)?;
+                        this.move_to(DVec3::new(packet.x, packet.y, packet.z), direction)?;
                     }
                     C2SPlayPackets::MovePlayerPosRot(packet) => {
                         if this.get(PlayerComponents::TELEPORT_SYNC_SENT).unwrap_or(0)
@@ -177,23 +149,10 @@ impl ConnectionData {
                         {
                             return Ok(());
                         }
-                        this.set(
-                            PlayerComponents::POSITION,
+                        this.move_to(
                             DVec3::new(packet.x, packet.y, packet.z),
-                        );
-                        this.set(
-                            PlayerComponents::DIRECTION,
                             Vec2::new(packet.pitch, packet.yaw),
-                        );
-
-                        this.connected_server.spawn_event(PlayerMoveEvent {
-                            player: this.as_actor(),
-                            new_position: this.get(PlayerComponents::POSITION)?,
-                            new_direction: this.get(PlayerComponents::DIRECTION)?,
-                        })?;
-
-                        this.update_self_entity()?;
-                        this.send_chunks()?;
+                        )?;
                     }
                     C2SPlayPackets::MovePlayerRot(packet) => {
                         if this.get(PlayerComponents::TELEPORT_SYNC_SENT).unwrap_or(0)
@@ -203,18 +162,8 @@ impl ConnectionData {
                         {
                             return Ok(());
                         }
-                        this.set(
-                            PlayerComponents::DIRECTION,
-                            Vec2::new(packet.pitch, packet.yaw),
-                        );
-
-                        this.connected_server.spawn_event(PlayerMoveEvent {
-                            player: this.as_actor(),
-                            new_position: this.get(PlayerComponents::POSITION)?,
-                            new_direction: this.get(PlayerComponents::DIRECTION)?,
-                        })?;
-
-                        this.update_self_entity()?;
+                        let position = this.get(PlayerComponents::POSITION)?;
+                        this.move_to(position, Vec2::new(packet.pitch, packet.yaw))?;
                     }
                     C2SPlayPackets::ClientInformation(packet) => {
                         this.associated_data.render_distance = packet.info.view_distance as i32;
@@ -228,7 +177,17 @@ impl ConnectionData {
                     }
                     C2SPlayPackets::ChunkBatchReceived(_packet) => {}
                     C2SPlayPackets::SetCreativeModeSlot(packet) => {
-                        let item_id = ITEM_REGISTRY.lookup(&packet.new_item.id).unwrap();
+                        if !(0..46).contains(&packet.slot) {
+                            log::warn!("Received an invalid creative slot {:?}", packet.slot);
+                            return Ok(());
+                        }
+                        let Some(item_id) = ITEM_REGISTRY.lookup(&packet.new_item.id) else {
+                            log::warn!(
+                                "Received an unknown creative item {:?}",
+                                packet.new_item.id
+                            );
+                            return Ok(());
+                        };
                         let stack = ItemStack::from(packet.new_item)
                             .with(ItemComponents::ITEM_MODEL, item_id.id.clone().into())
                             .with(
@@ -272,65 +231,34 @@ impl ConnectionData {
                                 .get_slot(this.associated_data.held_slot as usize)?;
 
                             let state = BlockState::new(held.kind());
-                            let state_clone = state.clone();
-                            let dim = this
-                                .associated_data
-                                .dimension
-                                .as_ref()
-                                .ok_or(ActorError::ActorIsNotLoaded)?
-                                .clone();
-
-                            this.write_packet(BlockChangedAckS2CPlayPacket(packet.sequence));
-                            Runtime::spawn_task(async move {
-                                let _ = dim.set_block(final_pos, state_clone);
-
-                                Ok(())
-                            });
-
                             if state.id_is_valid() {
-                                if let Ok(item_count) = held.get(ItemComponents::ITEM_COUNT) {
-                                    if item_count <= 1 {
-                                        this.associated_data.inventory.set_slot(
-                                            this.associated_data.held_slot as usize,
-                                            ItemStack::air(),
-                                        )?;
-                                    } else {
-                                        this.associated_data.inventory.set_slot(
-                                            this.associated_data.held_slot as usize,
-                                            held.with(ItemComponents::ITEM_COUNT, item_count - 1),
-                                        )?;
-                                    }
-                                }
-                            }
-
-                            if state.id_is_valid() {
-                                this.connected_server.spawn_event(PlaceBlockEvent {
-                                    player: this.as_actor(),
-                                    position: final_pos,
-                                    block: state,
-                                })?;
+                                this.place_block(final_pos, state, held)?;
                             } else {
                                 this.connected_server.spawn_event(RightClickEvent {
                                     player: this.as_actor(),
                                 })?;
                             }
+                            this.write_packet(BlockChangedAckS2CPlayPacket(packet.sequence));
                         }
                     }
                     C2SPlayPackets::Chat(packet) => {
-                        this.connected_server.spawn_event(ChatMessageEvent {
-                            player: this.as_actor(),
-                            message: packet.message,
-                        })?;
+                        this.chat(packet.message)?;
                     }
                     C2SPlayPackets::ContainerClick(packet) => {
                         this.associated_data.cursor_item = packet.cursor_item.into();
 
                         if let Some((screen, open_inventory)) = &mut this.associated_data.screen {
                             for slot in packet.changed_slots.iter() {
-                                match ScreenWindowKind::from(*screen)
-                                    .get_slot_index_group(slot.slot as usize)
-                                    .unwrap()
-                                {
+                                let Some(group) = usize::try_from(slot.slot).ok().and_then(|idx| {
+                                    ScreenWindowKind::from(*screen).get_slot_index_group(idx)
+                                }) else {
+                                    log::warn!(
+                                        "Received an invalid container slot {:?}",
+                                        slot.slot
+                                    );
+                                    continue;
+                                };
+                                match group {
                                     ContainerSlotGroup::PlayerHotbar(hotbar) => {
                                         this.associated_data
                                             .inventory
@@ -345,11 +273,15 @@ impl ConnectionData {
                                         open_inventory
                                             .set_slot(slot_idx, slot.data.clone().into())?;
                                     }
-                                    _ => todo!(),
+                                    // Other slot groups are not tracked by the server.
+                                    _ => {}
                                 }
                             }
                         } else {
                             for slot in packet.changed_slots.iter() {
+                                if !(0..46).contains(&slot.slot) {
+                                    continue;
+                                }
                                 this.associated_data
                                     .inventory
                                     .set_slot(slot.slot as usize, slot.data.clone().into())?;
@@ -475,6 +407,21 @@ impl ConnectionData {
                             // todo: send statistics packet
                         }
                     },
+                    C2SPlayPackets::KeepAlive(packet) => {
+                        this.receive_keep_alive(packet.0)?;
+                    }
+                    C2SPlayPackets::CustomPayload(packet) => {
+                        this.receive_plugin_message(
+                            packet.channel.into(),
+                            packet.data.as_slice().to_vec(),
+                        )?;
+                    }
+                    C2SPlayPackets::CookieResponse(packet) => {
+                        this.receive_cookie(
+                            packet.key.into(),
+                            packet.payload.map(|payload| payload.as_slice().to_vec()),
+                        );
+                    }
                     packet => {
                         log::warn!(
                             "Received unknown play packet, this packet will be ignored. {:?}",
@@ -491,23 +438,13 @@ impl ConnectionData {
     pub fn connect_to_new_dimension(&mut self) -> ActorResult<()> {
         log::debug!("Setting dimension...");
 
-        let key = Id::constant("null", "null");
+        // Players whose dimension no handler sets are kicked below.
         let token = Token::new(Id::constant("null", "null"));
-        let token_copy = token.clone();
-
-        self.connected_server.spawn_event(PlayerJoinEvent {
+        self.fire_event_blocking(PlayerJoinEvent {
             player: self.as_actor(),
-            new_dimension: token_copy,
+            new_dimension: token.clone(),
         })?;
 
-        loop {
-            self.handle_messages();
-
-            if token.get() != key {
-                break;
-            }
-        }
-
         self.associated_data.dimension = self.connected_server.dimension(token.get()).ok();
 
         if self.associated_data.dimension.is_none() {
@@ -531,19 +468,7 @@ impl ConnectionData {
         for player in self.connected_server.connections()? {
             let uuid = self.get(PlayerComponents::UUID)?;
             let username = self.get(PlayerComponents::USERNAME)?;
-            let props = if let Some(mojauth) = self.mojauth.as_ref() {
-                mojauth
-                    .props
-                    .iter()
-                    .map(|x| ProfileProperty {
-                        name: x.name.clone(),
-                        value: x.value.clone(),
-                        sig: Some(x.sig.clone()),
-                    })
-                    .collect::<Vec<_>>()
-            } else {
-                Vec::new()
-            };
+            let props = self.profile_properties.clone();
 
             Runtime::spawn_task(async move {
                 let _ = player.write_packet(PlayerInfoUpdateS2CPlayPacket {
@@ -569,19 +494,7 @@ impl ConnectionData {
         let username = self.get(PlayerComponents::USERNAME)?;
         for player in self.connected_server.connections()? {
             if player.sender.upgrade().unwrap().same_channel(&self.sender) {
-                let props = if let Some(mojauth) = self.mojauth.as_ref() {
-                    mojauth
-                        .props
-                        .iter()
-                        .map(|x| ProfileProperty {
-                            name: x.name.clone(),
-                            value: x.value.clone(),
-                            sig: Some(x.sig.clone()),
-                        })
-                        .collect::<Vec<_>>()
-                } else {
-                    Vec::new()
-                };
+                let props = self.profile_properties.clone();
 
                 self.write_packet(PlayerInfoUpdateS2CPlayPacket {
                     actions: vec![(
@@ -687,4 +600,143 @@ impl ConnectionData {
 
         Ok(())
     }
+
+    /// Breaks a block unless a `BreakBlockEvent` handler cancels it, in which case the
+    /// block is sent back to the player.
+    fn break_block(&mut self, position: IVec3) -> ActorResult<()> {
+        let dim = self
+            .associated_data
+            .dimension
+            .clone()
+            .ok_or(ActorError::ActorIsNotLoaded)?;
+        let old_block = dim.get_block(position)?;
+
+        let cancelled = self.fire_cancellable_event(BreakBlockEvent {
+            player: self.as_actor(),
+            position,
+            old_block: old_block.clone(),
+            cancelled: Token::new(false),
+        })?;
+        if cancelled {
+            self.resend_block(position, &old_block);
+            return Ok(());
+        }
+        dim.set_block(position, BlockState::new(Id::constant("minecraft", "air")))
+    }
+
+    /// Places the held block unless a `PlaceBlockEvent` handler cancels it, in which case
+    /// the block and the held item are sent back to the player.
+    fn place_block(
+        &mut self,
+        position: IVec3,
+        state: BlockState,
+        held: ItemStack,
+    ) -> ActorResult<()> {
+        let dim = self
+            .associated_data
+            .dimension
+            .clone()
+            .ok_or(ActorError::ActorIsNotLoaded)?;
+        let held_slot = self.associated_data.held_slot as usize;
+
+        let cancelled = self.fire_cancellable_event(PlaceBlockEvent {
+            player: self.as_actor(),
+            position,
+            block: state.clone(),
+            cancelled: Token::new(false),
+        })?;
+        if cancelled {
+            let current = dim.get_block(position)?;
+            self.resend_block(position, &current);
+            return self.set_inv_slot(held_slot, held);
+        }
+
+        Runtime::spawn_task(async move {
+            let _ = dim.set_block(position, state);
+
+            Ok(())
+        });
+        if let Ok(item_count) = held.get(ItemComponents::ITEM_COUNT) {
+            if item_count <= 1 {
+                self.associated_data
+                    .inventory
+                    .set_slot(held_slot, ItemStack::air())?;
+            } else {
+                self.associated_data.inventory.set_slot(
+                    held_slot,
+                    held.with(ItemComponents::ITEM_COUNT, item_count - 1),
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Drops the held item unless a `DropItemEvent` handler cancels it, in which case the
+    /// item is put back in the player's hand.
+    fn drop_held_item(&mut self) -> ActorResult<()> {
+        let held_slot = self.associated_data.held_slot as usize;
+        let item = self.get_inv_slot(held_slot)?;
+
+        let cancelled = self.fire_cancellable_event(DropItemEvent {
+            player: self.as_actor(),
+            item: item.clone(),
+            cancelled: Token::new(false),
+        })?;
+        if cancelled {
+            // The client has already removed the item, so it has to be sent again.
+            return self.set_inv_slot(held_slot, item);
+        }
+        self.set_inv_slot(held_slot, ItemStack::air())
+    }
+
+    /// Moves the player unless a `PlayerMoveEvent` handler cancels it, in which case the
+    /// player is teleported back to where they were and turned back to where they looked.
+    fn move_to(&mut self, position: DVec3, direction: Vec2) -> ActorResult<()> {
+        let old_position = self.get(PlayerComponents::POSITION)?;
+        let old_direction = self.get(PlayerComponents::DIRECTION)?;
+
+        let cancelled = self.fire_cancellable_event(PlayerMoveEvent {
+            player: self.as_actor(),
+            new_position: position,
+            new_direction: direction,
+            cancelled: Token::new(false),
+        })?;
+        if cancelled {
+            if position != old_position {
+                self.set(PlayerComponents::TELEPORT_POSITION, old_position);
+            }
+            if direction != old_direction {
+                self.write_packet(PlayerRotationS2CPlayPacket {
+                    yaw: old_direction[1],
+                    pitch: old_direction[0],
+                });
+            }
+            return Ok(());
+        }
+
+        self.set(PlayerComponents::POSITION, position);
+        self.set(PlayerComponents::DIRECTION, direction);
+        self.update_self_entity()?;
+        if position != old_position {
+            self.send_chunks()?;
+        }
+        Ok(())
+    }
+
+    /// Fires a `ChatMessageEvent`. Showing the message is left up to its handlers.
+    fn chat(&mut self, message: String) -> ActorResult<()> {
+        self.fire_cancellable_event(ChatMessageEvent {
+            player: self.as_actor(),
+            message,
+            cancelled: Token::new(false),
+        })?;
+        Ok(())
+    }
+
+    fn resend_block(&mut self, position: IVec3, block: &BlockState) {
+        self.write_packet(BlockUpdateS2CPlayPacket {
+            pos: BlockPos::new(position[0], position[1], position[2]),
+            block: unsafe { RegEntry::new_unchecked(block.protocol_id() as u32) },
+        });
+    }
 }
@@ -1,15 +1,11 @@
-use voxidian_protocol::{
-    packet::{
-        c2s::status::C2SStatusPackets,
-        s2c::status::{
-            PongResponseS2CStatusPacket, StatusResponse, StatusResponsePlayers,
-            StatusResponseVersion,
-        },
-    },
-    value::Text,
+use voxidian_protocol::packet::{
+    c2s::status::C2SStatusPackets, s2c::status::PongResponseS2CStatusPacket,
 };
+use wyvern_values::cell::Token;
 
-use crate::{actors::ActorResult, player::ConnectionData};
+use crate::{
+    actors::ActorResult, events::ServerStatusEvent, player::ConnectionData, server::ServerStatus,
+};
 
 impl ConnectionData {
     pub fn status_stage(&mut self) -> ActorResult<()> {
@@ -17,24 +13,14 @@ impl ConnectionData {
             log::debug!("Packet: {:?}", packet);
             match packet {
                 C2SStatusPackets::StatusRequest(_packet) => {
-                    this.write_packet(
-                        StatusResponse {
-                            version: StatusResponseVersion {
-                                name: "1.21.4".to_string(),
-                                protocol: 769,
-                            },
-                            players: Some(StatusResponsePlayers {
-                                online: 0,
-                                max: 100,
-                                sample: vec![],
-                            }),
-                            desc: Text::new(),
-                            favicon_png_b64: "".to_string(),
-                            enforce_chat_reports: false,
-                            prevent_chat_reports: true,
-                        }
-                        .to_packet(),
-                    );
+                    let status = Token::new(ServerStatus::collect(&this.connected_server)?);
+                    this.fire_event_blocking(ServerStatusEvent {
+                        server: this.connected_server.clone(),
+                        remote_addr: this.addr,
+                        protocol_version: this.associated_data.protocol_version,
+                        status: status.clone(),
+                    })?;
+                    this.write_packet(status.get().into_response().to_packet());
                 }
                 C2SStatusPackets::PingRequest(packet) => {
                     this.write_packet(PongResponseS2CStatusPacket {
@@ -1,20 +1,38 @@
-use std::{collections::HashMap, ops::Deref, sync::Arc, thread::Builder, time::Instant};
+use std::{
+    collections::HashMap,
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4, TcpListener},
+    ops::Deref,
+    sync::Arc,
+    thread::Builder,
+    time::Duration,
+};
 
 use voxidian_protocol::autogenerated::block_states::{
     BLOCK_STATE_DEFAULTS, BLOCK_STATE_TO_ID, BLOCK_STATES, ID_TO_BLOCK_STATE,
 };
+use wyvern_datatypes::text::Text;
 use wyvern_textures::TexturePack;
 use wyvern_values::Id;
 
 use crate::{
     actors::ActorResult,
     blocks::BLOCK_STATE_KEYS,
-    events::{Event, EventBus},
+    commands::{CommandNode, CommandRegistry},
+    events::{DispatchMode, Event, EventBus, EventPriority},
+    permissions::{MemoryPermissionStorage, PermissionStorage},
+    player::{
+        Compression, DEFAULT_KEEP_ALIVE_TIMEOUT, DEFAULT_MAX_QUEUED_BYTES, DEFAULT_SESSION_SERVER,
+        InboundPacket, OutgoingPacket, PacketContext, PacketDecision, PacketListeners,
+        ProfileResolver,
+    },
     plugin::Plugin,
-    runtime::{GLOBAL_RUNTIME, NeverYield},
+    runtime::GLOBAL_RUNTIME,
 };
 
-use super::{ServerData, dimensions::DimensionContainer, registries::RegistryContainer};
+use super::{
+    ForwardingMode, RconConfig, ServerData, TickScheduler, dimensions::DimensionContainer,
+    registries::RegistryContainer,
+};
 
 pub struct ServerBuilder {
     events: EventBus,
@@ -23,6 +41,24 @@ pub struct ServerBuilder {
     mojauth_enabled: bool,
     texture_pack: Option<TexturePack>,
     task_threads: usize,
+    listen_addrs: Vec<SocketAddr>,
+    max_connections: Option<usize>,
+    motd: Text,
+    favicon: Option<Vec<u8>>,
+    forwarding: ForwardingMode,
+    profile_resolver: Option<Arc<dyn ProfileResolver>>,
+    session_server: String,
+    accept_transfers: bool,
+    plugin_channels: Vec<Id>,
+    packet_listeners: PacketListeners,
+    commands: CommandRegistry,
+    permissions: Arc<dyn PermissionStorage>,
+    compression: Compression,
+    keep_alive_timeout: Duration,
+    max_queued_bytes: usize,
+    rcon: Option<RconConfig>,
+    query: Option<SocketAddr>,
+    console: bool,
 }
 
 impl Default for ServerBuilder {
@@ -42,6 +78,24 @@ impl ServerBuilder {
             mojauth_enabled: true,
             texture_pack: None,
             task_threads: 1,
+            listen_addrs: Vec::new(),
+            max_connections: None,
+            motd: Text::literal(""),
+            favicon: None,
+            forwarding: ForwardingMode::None,
+            profile_resolver: None,
+            session_server: DEFAULT_SESSION_SERVER.to_string(),
+            accept_transfers: false,
+            plugin_channels: Vec::new(),
+            packet_listeners: PacketListeners::default(),
+            commands: CommandRegistry::default(),
+            permissions: Arc::new(MemoryPermissionStorage::default()),
+            compression: Compression::default(),
+            keep_alive_timeout: DEFAULT_KEEP_ALIVE_TIMEOUT,
+            max_queued_bytes: DEFAULT_MAX_QUEUED_BYTES,
+            rcon: None,
+            query: None,
+            console: false,
         }
     }
 
@@ -49,12 +103,46 @@ impl ServerBuilder {
         E: Event + 'static,
         F: 'static + Future<Output = ActorResult<()>> + Send + Sync,
         N: 'static + Fn(Arc<E>) -> F + Send + Sync,
+    >(
+        self,
+        f: N,
+    ) -> Self {
+        self.event_with_priority(EventPriority::Normal, f)
+    }
+
+    /// Adds an event handler that runs before handlers of higher priorities, and after
+    /// handlers of lower ones.
+    pub fn event_with_priority<
+        E: Event + 'static,
+        F: 'static + Future<Output = ActorResult<()>> + Send + Sync,
+        N: 'static + Fn(Arc<E>) -> F + Send + Sync,
     >(
         mut self,
+        priority: EventPriority,
         f: N,
     ) -> Self {
-        E::add_handler(&mut self.events, f);
+        E::add_handler(&mut self.events, priority, f);
+
+        self
+    }
+
+    /// Sets how the handlers of an event are run. Defaults to `DispatchMode::Sequential`.
+    pub fn event_dispatch(mut self, mode: DispatchMode) -> Self {
+        self.events.mode = mode;
+        self
+    }
 
+    /// Registers a command, which must start with a literal node. Registered commands are
+    /// sent to players for completion, and no longer fire `CommandEvent`.
+    pub fn command(mut self, command: CommandNode) -> Self {
+        self.commands.register(command);
+        self
+    }
+
+    /// Sets where player and group permissions are stored. Defaults to memory, which
+    /// forgets every permission when the server stops.
+    pub fn permissions(mut self, storage: impl PermissionStorage + 'static) -> Self {
+        self.permissions = Arc::new(storage);
         self
     }
 
@@ -72,6 +160,133 @@ impl ServerBuilder {
         self
     }
 
+    /// Adds an address for the server to listen on. Can be called multiple times.
+    /// Defaults to `127.0.0.1:25565` if no address is given.
+    pub fn bind(mut self, addr: impl Into<SocketAddr>) -> Self {
+        self.listen_addrs.push(addr.into());
+        self
+    }
+
+    /// Limits how many connections may be open at once. Every connection runs on its own
+    /// thread, so this also limits how many threads the server uses for players.
+    pub fn max_connections(mut self, max: usize) -> Self {
+        self.max_connections = Some(max);
+        self
+    }
+
+    /// Sets the default description shown in the server list.
+    pub fn motd(mut self, motd: Text) -> Self {
+        self.motd = motd;
+        self
+    }
+
+    /// Sets the default server list icon. This must be a 64x64 PNG image.
+    pub fn favicon(mut self, png: Vec<u8>) -> Self {
+        self.favicon = Some(png);
+        self
+    }
+
+    /// Sets the base URL of the session server used to authenticate players.
+    /// Defaults to Mojang's session server.
+    pub fn session_server(mut self, url: impl Into<String>) -> Self {
+        self.session_server = url.into();
+        self
+    }
+
+    /// Sets where player profiles come from when Mojang authentication is disabled.
+    /// Players not known to the resolver get vanilla offline-mode UUIDs.
+    pub fn profile_resolver<R: ProfileResolver + 'static>(mut self, resolver: R) -> Self {
+        self.profile_resolver = Some(Arc::new(resolver));
+        self
+    }
+
+    /// Sets how player information is forwarded from a proxy in front of this server.
+    /// When forwarding is enabled, authentication is left to the proxy.
+    pub fn forwarding(mut self, mode: ForwardingMode) -> Self {
+        self.forwarding = mode;
+        self
+    }
+
+    /// Sets whether players transferred from another server may join. Defaults to `false`.
+    pub fn accept_transfers(mut self, accept: bool) -> Self {
+        self.accept_transfers = accept;
+        self
+    }
+
+    /// Registers a plugin channel. `PluginMessageEvent`s are only fired for registered
+    /// channels, which are announced to clients through `minecraft:register`.
+    pub fn plugin_channel(mut self, channel: Id) -> Self {
+        self.plugin_channels.push(channel);
+        self
+    }
+
+    /// Sets how packets are compressed. Defaults to zlib at level 6 for packets of at least
+    /// 128 bytes. `PlayerPreLoginEvent` handlers can override this per connection.
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Sets how long a player may take to answer a keep alive before they are disconnected.
+    /// Defaults to 30 seconds.
+    pub fn keep_alive_timeout(mut self, timeout: Duration) -> Self {
+        self.keep_alive_timeout = timeout;
+        self
+    }
+
+    /// Sets how many bytes may be waiting to be sent to a player before they are considered
+    /// too slow and disconnected. Defaults to 16 MiB.
+    pub fn max_queued_bytes(mut self, max: usize) -> Self {
+        self.max_queued_bytes = max;
+        self
+    }
+
+    /// Sets whether commands are read from standard input. Defaults to `false`.
+    ///
+    /// A process only ever has one reader, which keeps running after the server stops and
+    /// runs commands on whichever server with the console enabled is running at the time.
+    pub fn console(mut self, enabled: bool) -> Self {
+        self.console = enabled;
+        self
+    }
+
+    /// Enables RCON on the given address. Commands sent over RCON run with a console sender,
+    /// and their output is sent back as plain text.
+    pub fn rcon(mut self, addr: impl Into<SocketAddr>, password: impl Into<String>) -> Self {
+        self.rcon = Some(RconConfig {
+            addr: addr.into(),
+            password: password.into(),
+        });
+        self
+    }
+
+    /// Enables the UDP query protocol on the given address, so server lists can look up the
+    /// players, MOTD and map. The MOTD and player counts match the status response.
+    pub fn query(mut self, addr: impl Into<SocketAddr>) -> Self {
+        self.query = Some(addr.into());
+        self
+    }
+
+    /// Adds a listener that sees every packet of type `T` a client sends, before the server
+    /// handles it. The listener may modify the packet, or cancel it so it is never handled.
+    pub fn inbound_packet_listener<T: InboundPacket>(
+        mut self,
+        f: impl Fn(&PacketContext, &mut T) -> PacketDecision + Send + Sync + 'static,
+    ) -> Self {
+        self.packet_listeners.add_inbound(Box::new(f));
+        self
+    }
+
+    /// Adds a listener that sees every packet before it is sent to a client.
+    /// The listener may modify the packet, or cancel it so it is never sent.
+    pub fn outbound_packet_listener(
+        mut self,
+        f: impl Fn(&PacketContext, &mut OutgoingPacket) -> PacketDecision + Send + Sync + 'static,
+    ) -> Self {
+        self.packet_listeners.add_outbound(Box::new(f));
+        self
+    }
+
     pub fn registries<F: FnOnce(&mut RegistryContainer)>(mut self, f: F) -> Self {
         f(&mut self.registries);
         self
@@ -82,13 +297,33 @@ impl ServerBuilder {
         self
     }
 
-    pub fn run(self) {
+    /// Starts the server and blocks until it has stopped. Fails if the server can not
+    /// listen on one of its addresses.
+    pub fn run(mut self) -> std::io::Result<()> {
+        if self.listen_addrs.is_empty() {
+            self.listen_addrs.push(SocketAddr::V4(SocketAddrV4::new(
+                Ipv4Addr::new(127, 0, 0, 1),
+                25565,
+            )));
+        }
+
+        let listeners = self
+            .listen_addrs
+            .iter()
+            .map(|addr| {
+                TcpListener::bind(addr).map_err(|err| {
+                    std::io::Error::new(err.kind(), format!("failed to bind to {}: {}", addr, err))
+                })
+            })
+            .collect::<std::io::Result<Vec<_>>>()?;
+
         let chan = flume::unbounded();
+        let (worker_shutdown, worker_signal) = flume::bounded::<()>(0);
         let server = ServerData {
             connections: Vec::new(),
             registries: Arc::new(self.registries),
             dimensions: self.dimensions,
-            last_tick: Instant::now(),
+            ticks: TickScheduler::new(),
 
             sender: chan.0,
             receiver: chan.1,
@@ -99,6 +334,30 @@ impl ServerBuilder {
             texture_pack: self.texture_pack.map(Arc::new),
 
             default_dimension: Id::constant("minecraft", "overworld"),
+            local_addrs: listeners
+                .iter()
+                .filter_map(|listener| listener.local_addr().ok())
+                .collect(),
+            max_connections: self.max_connections,
+            motd: self.motd,
+            favicon: self.favicon,
+            forwarding: self.forwarding,
+            profile_resolver: self.profile_resolver,
+            session_server: self.session_server,
+            accept_transfers: self.accept_transfers,
+            plugin_channels: self.plugin_channels,
+            packet_listeners: Arc::new(self.packet_listeners),
+            commands: Arc::new(self.commands),
+            permissions: self.permissions,
+            compression: self.compression,
+            keep_alive_timeout: self.keep_alive_timeout,
+            max_queued_bytes: self.max_queued_bytes,
+            rcon: self.rcon,
+            query: self.query,
+            console: self.console,
+            stop_reason: None,
+            pending_latencies: HashMap::new(),
+            worker_shutdown: Some(worker_shutdown),
         };
 
         log::info!("Initializing some lazy values...");
@@ -109,12 +368,67 @@ impl ServerBuilder {
         let _ = ID_TO_BLOCK_STATE.deref();
         let _ = BLOCK_STATE_KEYS.deref();
 
-        for _ in 0..self.task_threads {
-            let _ = Builder::new().name("AsyncEventLoop".into()).spawn(|| {
-                futures::executor::block_on(GLOBAL_RUNTIME.executor.run(NeverYield));
-            });
+        let workers = (0..self.task_threads)
+            .filter_map(|_| {
+                let worker_signal = worker_signal.clone();
+                Builder::new()
+                    .name("AsyncEventLoop".into())
+                    .spawn(move || {
+                        let _ = futures::executor::block_on(
+                            GLOBAL_RUNTIME.executor.run(worker_signal.recv_async()),
+                        );
+                    })
+                    .ok()
+            })
+            .collect::<Vec<_>>();
+
+        let result = server.start(listeners);
+
+        for worker in workers {
+            let _ = worker.join();
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{Ipv4Addr, SocketAddr},
+        sync::Arc,
+    };
+
+    use wyvern_datatypes::text::Text;
+
+    use crate::{events::ServerStartEvent, server::Server};
+
+    #[test]
+    fn servers_on_port_zero_run_side_by_side() {
+        let (addrs_tx, addrs_rx) = flume::unbounded();
+        let servers = (0..2)
+            .map(|_| {
+                let addrs_tx = addrs_tx.clone();
+                std::thread::spawn(move || {
+                    Server::builder()
+                        .bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
+                        .event(move |event: Arc<ServerStartEvent>| {
+                            let addrs_tx = addrs_tx.clone();
+                            async move {
+                                let _ = addrs_tx.send(event.server.local_addrs()?);
+                                event.server.shutdown(Text::literal("Test finished"))
+                            }
+                        })
+                        .run()
+                })
+            })
+            .collect::<Vec<_>>();
+        for server in servers {
+            server.join().unwrap().unwrap();
         }
 
-        server.start();
+        let addrs = addrs_rx.try_iter().flatten().collect::<Vec<_>>();
+        assert_eq!(addrs.len(), 2);
+        assert!(addrs.iter().all(|addr| addr.port() != 0));
+        assert_ne!(addrs[0], addrs[1]);
     }
 }
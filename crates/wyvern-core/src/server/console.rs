@@ -0,0 +1,67 @@
+use std::{io::BufRead, sync::Once};
+
+use crate::{
+    commands::{CommandSender, ConsoleSender, ConsoleSource},
+    events::{CommandEvent, Event},
+    runtime::Runtime,
+};
+
+use super::Server;
+
+static CONSOLE_READER: Once = Once::new();
+
+/// Starts reading commands from the process's standard input in the background, unless an
+/// earlier server already did. Reading standard input can not be interrupted, so the reader
+/// outlives the server and passes commands on to whichever server is running.
+pub(crate) fn start_console() {
+    CONSOLE_READER.call_once(|| {
+        Runtime::spawn_actor(console_loop, "ConsoleThread");
+    });
+}
+
+fn console_loop() {
+    let console = ConsoleSender::new(ConsoleSource::Terminal);
+    for line in std::io::stdin().lock().lines() {
+        let Ok(line) = line else {
+            return;
+        };
+        let command = line.trim();
+        let command = command.strip_prefix('/').unwrap_or(command);
+        if command.is_empty() {
+            continue;
+        }
+
+        let server = match Server::get() {
+            Ok(server) if server.console_enabled().unwrap_or(false) => server,
+            _ => {
+                log::warn!(
+                    "No server with a console is running, ignoring {:?}",
+                    command
+                );
+                continue;
+            }
+        };
+        let Ok(bus) = server.event_bus() else {
+            continue;
+        };
+
+        // Commands run one at a time, so their output is never interleaved.
+        let sender = CommandSender::Console(console.clone());
+        let commands = server.commands().unwrap_or_default();
+        if commands.contains(command) {
+            let _ = futures::executor::block_on(commands.dispatch(
+                server.clone(),
+                sender,
+                command.to_string(),
+            ));
+            continue;
+        }
+        CommandEvent {
+            server: server.clone(),
+            sender,
+            command: command.to_string(),
+        }
+        .dispatch_tracked(bus)
+        .wait();
+    }
+}
@@ -0,0 +1,162 @@
+use std::{net::IpAddr, str::FromStr};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use voxidian_protocol::value::{ProfileProperty, Uuid};
+
+use crate::varint::PayloadReader;
+
+pub(crate) const VELOCITY_CHANNEL: &str = "velocity:player_info";
+pub(crate) const VELOCITY_MODERN_FORWARDING_VERSION: u8 = 1;
+
+/// How player information is forwarded to this server by a proxy in front of it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum ForwardingMode {
+    /// Players connect directly to this server.
+    #[default]
+    None,
+    /// Velocity's modern forwarding, authenticated by a secret shared with the proxy.
+    Velocity { secret: String },
+    /// BungeeCord's legacy forwarding through the handshake's host field.
+    /// This is not authenticated, so the server must not be reachable without the proxy.
+    BungeeCord,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct ForwardedPlayer {
+    pub(crate) addr: IpAddr,
+    pub(crate) uuid: Uuid,
+    pub(crate) username: Option<String>,
+    pub(crate) properties: Vec<ProfileProperty>,
+}
+
+/// Parses the host field of a handshake sent through BungeeCord, which has the form
+/// `host\0client_ip\0uuid\0properties_json`. The properties are optional.
+pub(crate) fn parse_bungeecord_host(host: &str) -> Option<ForwardedPlayer> {
+    let mut parts = host.split('\0');
+    let _host = parts.next()?;
+    let addr = IpAddr::from_str(parts.next()?).ok()?;
+    let uuid = Uuid::parse_str(parts.next()?).ok()?;
+
+    let mut properties = Vec::new();
+    if let Some(props) = parts.next() {
+        let json = json::parse(props).ok()?;
+        for prop in json.members() {
+            properties.push(ProfileProperty {
+                name: prop["name"].as_str()?.to_string(),
+                value: prop["value"].as_str()?.to_string(),
+                sig: prop["signature"].as_str().map(|x| x.to_string()),
+            });
+        }
+    }
+
+    Some(ForwardedPlayer {
+        addr,
+        uuid,
+        username: None,
+        properties,
+    })
+}
+
+/// Verifies and parses the response to a `velocity:player_info` login query.
+/// The response is an HMAC-SHA256 signature of the payload, followed by the payload itself.
+pub(crate) fn parse_velocity_response(secret: &[u8], data: &[u8]) -> Option<ForwardedPlayer> {
+    if data.len() < 32 {
+        return None;
+    }
+    let (signature, payload) = data.split_at(32);
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).ok()?;
+    mac.update(payload);
+    mac.verify_slice(signature).ok()?;
+
+    let mut reader = PayloadReader::new(payload);
+    let version = reader.varint()?;
+    if version < 1 {
+        return None;
+    }
+    let addr = IpAddr::from_str(&reader.string()?).ok()?;
+    let uuid = Uuid::from_u128(u128::from_be_bytes(reader.bytes(16)?.try_into().ok()?));
+    let username = reader.string()?;
+
+    let mut properties = Vec::new();
+    for _ in 0..reader.varint()? {
+        let name = reader.string()?;
+        let value = reader.string()?;
+        let sig = if reader.bytes(1)?[0] != 0 {
+            Some(reader.string()?)
+        } else {
+            None
+        };
+        properties.push(ProfileProperty { name, value, sig });
+    }
+
+    Some(ForwardedPlayer {
+        addr,
+        uuid,
+        username: Some(username),
+        properties,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use hmac::{Hmac, Mac};
+    use sha2::Sha256;
+    use voxidian_protocol::value::Uuid;
+
+    use super::{parse_bungeecord_host, parse_velocity_response};
+    use crate::varint::write_string;
+
+    fn velocity_payload(secret: &[u8]) -> Vec<u8> {
+        let mut payload = vec![1];
+        write_string(&mut payload, "10.0.0.5");
+        payload.extend_from_slice(&0x1234u128.to_be_bytes());
+        write_string(&mut payload, "Steve");
+        payload.push(1);
+        write_string(&mut payload, "textures");
+        write_string(&mut payload, "abc");
+        payload.push(1);
+        write_string(&mut payload, "sig");
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret).unwrap();
+        mac.update(&payload);
+        let mut data = mac.finalize().into_bytes().to_vec();
+        data.extend(payload);
+        data
+    }
+
+    #[test]
+    fn velocity_valid() {
+        let player = parse_velocity_response(b"secret", &velocity_payload(b"secret")).unwrap();
+        assert_eq!(player.addr.to_string(), "10.0.0.5");
+        assert_eq!(player.uuid, Uuid::from_u128(0x1234));
+        assert_eq!(player.username.as_deref(), Some("Steve"));
+        assert_eq!(player.properties.len(), 1);
+        assert_eq!(player.properties[0].sig.as_deref(), Some("sig"));
+    }
+
+    #[test]
+    fn velocity_wrong_secret() {
+        assert!(parse_velocity_response(b"other", &velocity_payload(b"secret")).is_none());
+    }
+
+    #[test]
+    fn bungeecord_host() {
+        let player = parse_bungeecord_host(
+            "localhost\0192.168.1.2\0069a79f444e94726a5befca90e38aaf5\0[{\"name\":\"textures\",\"value\":\"abc\",\"signature\":\"def\"}]",
+        )
+        .unwrap();
+        assert_eq!(player.addr.to_string(), "192.168.1.2");
+        assert_eq!(
+            player.uuid,
+            Uuid::parse_str("069a79f4-44e9-4726-a5be-fca90e38aaf5").unwrap()
+        );
+        assert_eq!(player.properties[0].value, "abc");
+    }
+
+    #[test]
+    fn bungeecord_missing_data() {
+        assert!(parse_bungeecord_host("localhost").is_none());
+    }
+}
@@ -1,56 +1,111 @@
 use std::{
-    net::{Ipv4Addr, SocketAddrV4},
-    sync::{Arc, Mutex, OnceLock},
+    collections::HashMap,
+    io::ErrorKind,
+    net::{SocketAddr, TcpListener},
+    sync::{Arc, Mutex},
     time::{Duration, Instant},
 };
 
 use crate::{
     actor,
     actors::{ActorError, ActorResult},
+    commands::CommandRegistry,
     message,
+    permissions::PermissionStorage,
     player::PlayerComponents,
 };
 use crate::{actors::Actor, runtime::Runtime};
 use dimensions::DimensionContainer;
-use flume::Sender;
+use flume::{Receiver, Selector, Sender};
+use mio::{Events, Interest, Poll, Token};
 use registries::RegistryContainer;
-use voxidian_protocol::{packet::Stage, value::Uuid};
+use voxidian_protocol::{
+    packet::{
+        Stage,
+        s2c::play::{PlayerActionEntry, PlayerInfoUpdateS2CPlayPacket},
+    },
+    value::{Uuid, VarInt},
+};
+use wyvern_datatypes::text::Text;
 use wyvern_textures::TexturePack;
 
 use crate::{
     dimension::{Dimension, DimensionData},
-    events::{DimensionCreateEvent, Event, EventBus, ServerStartEvent, ServerTickEvent},
-    player::{ConnectionData, ConnectionWithSignal, Player},
+    events::{
+        DimensionCreateEvent, Event, EventBus, ServerStartEvent, ServerStopEvent, ServerTickEvent,
+    },
+    player::{
+        Compression, ConnectionData, ConnectionWithSignal, PacketListeners, Player, ProfileResolver,
+    },
 };
 use wyvern_values::Id;
 
 mod builder;
 pub use builder::*;
+mod console;
+pub(crate) use console::*;
 pub mod dimensions;
+mod forwarding;
+pub use forwarding::*;
+mod query;
+pub(crate) use query::*;
+mod rcon;
+pub(crate) use rcon::*;
+mod reactor;
 pub mod registries;
+pub(crate) use reactor::*;
+mod status;
+pub use status::*;
+mod tick;
+pub(crate) use tick::*;
 
-static SERVER_INSTANCE: OnceLock<Server> = OnceLock::new();
+static SERVER_INSTANCE: Mutex<Option<Server>> = Mutex::new(None);
+/// How long a stopping server waits for kicked players to disconnect.
+const KICK_TIMEOUT: Duration = Duration::from_secs(5);
 
 #[actor(Server, ServerMessage)]
 pub(crate) struct ServerData {
     pub(crate) connections: Vec<ConnectionWithSignal>,
     pub(crate) registries: Arc<RegistryContainer>,
     pub(crate) dimensions: DimensionContainer,
-    pub(crate) last_tick: Instant,
+    pub(crate) ticks: TickScheduler,
     pub(crate) sender: Sender<ServerMessage>,
     pub(crate) events: Arc<EventBus>,
     pub(crate) last_entity_id: i32,
     pub(crate) mojauth_enabled: bool,
     pub(crate) texture_pack: Option<Arc<TexturePack>>,
     pub(crate) default_dimension: Id,
+    pub(crate) local_addrs: Vec<SocketAddr>,
+    pub(crate) max_connections: Option<usize>,
+    pub(crate) motd: Text,
+    pub(crate) favicon: Option<Vec<u8>>,
+    pub(crate) forwarding: ForwardingMode,
+    pub(crate) profile_resolver: Option<Arc<dyn ProfileResolver>>,
+    pub(crate) session_server: String,
+    pub(crate) accept_transfers: bool,
+    pub(crate) plugin_channels: Vec<Id>,
+    pub(crate) packet_listeners: Arc<PacketListeners>,
+    pub(crate) commands: Arc<CommandRegistry>,
+    pub(crate) permissions: Arc<dyn PermissionStorage>,
+    pub(crate) compression: Compression,
+    pub(crate) keep_alive_timeout: Duration,
+    pub(crate) max_queued_bytes: usize,
+    pub(crate) rcon: Option<RconConfig>,
+    pub(crate) query: Option<SocketAddr>,
+    pub(crate) console: bool,
+    pub(crate) stop_reason: Option<Text>,
+    /// Latencies reported by players since the last tick, in milliseconds.
+    pub(crate) pending_latencies: HashMap<Uuid, i32>,
+    pub(crate) worker_shutdown: Option<Sender<()>>,
 }
 
 impl Server {
     pub fn get() -> ActorResult<Server> {
         SERVER_INSTANCE
-            .get()
+            .lock()
+            .unwrap()
+            .clone()
             .ok_or(ActorError::ActorDoesNotExist)
-            .cloned()
     }
 
     pub fn spawn_event<E: Event + Send + Sync + 'static>(&self, event: E) -> ActorResult<()> {
@@ -70,6 +125,13 @@ impl ServerData {
         Ok(self.default_dimension.clone())
     }
 
+    #[Shutdown]
+    #[doc = "Stops the server, disconnecting every player with the given reason. `ServerBuilder::run` returns once the server has stopped."]
+    pub fn shutdown(&mut self, reason: Text) -> ActorResult<()> {
+        self.stop_reason = Some(reason);
+        Ok(())
+    }
+
     #[SetDefaultDimension]
     pub fn set_default_dimension(&mut self, id: Id) -> ActorResult<()> {
         self.default_dimension = id;
@@ -86,6 +148,85 @@ impl ServerData {
         Ok(self.mojauth_enabled)
     }
 
+    #[GetForwardingMode]
+    pub fn forwarding_mode(&self) -> ActorResult<ForwardingMode> {
+        Ok(self.forwarding.clone())
+    }
+
+    #[GetProfileResolver]
+    pub fn profile_resolver(&self) -> ActorResult<Option<Arc<dyn ProfileResolver>>> {
+        Ok(self.profile_resolver.clone())
+    }
+
+    #[GetSessionServer]
+    pub fn session_server(&self) -> ActorResult<String> {
+        Ok(self.session_server.clone())
+    }
+
+    #[GetTps]
+    #[doc = "Returns the average ticks per second over the last few seconds, which is at most 20."]
+    pub fn tps(&self) -> ActorResult<f64> {
+        Ok(self.ticks.tps())
+    }
+
+    #[GetMspt]
+    #[doc = "Returns the average time in milliseconds that a tick, including its `ServerTickEvent` handlers, took over the last few seconds."]
+    pub fn mspt(&self) -> ActorResult<f64> {
+        Ok(self.ticks.mspt())
+    }
+
+    #[CurrentTick]
+    #[doc = "Returns the number of the tick the server is currently on. The first tick is 1."]
+    pub fn current_tick(&self) -> ActorResult<u64> {
+        Ok(self.ticks.current_tick())
+    }
+
+    #[AcceptsTransfers]
+    pub fn accepts_transfers(&self) -> ActorResult<bool> {
+        Ok(self.accept_transfers)
+    }
+
+    #[PluginChannels]
+    pub fn plugin_channels(&self) -> ActorResult<Vec<Id>> {
+        Ok(self.plugin_channels.clone())
+    }
+
+    #[GetCompression]
+    pub fn compression(&self) -> ActorResult<Compression> {
+        Ok(self.compression)
+    }
+
+    #[GetKeepAliveTimeout]
+    pub fn keep_alive_timeout(&self) -> ActorResult<Duration> {
+        Ok(self.keep_alive_timeout)
+    }
+
+    #[GetMaxQueuedBytes]
+    pub fn max_queued_bytes(&self) -> ActorResult<usize> {
+        Ok(self.max_queued_bytes)
+    }
+
+    #[GetCommands]
+    pub(crate) fn commands(&self) -> ActorResult<Arc<CommandRegistry>> {
+        Ok(self.commands.clone())
+    }
+
+    #[ConsoleEnabled]
+    pub(crate) fn console_enabled(&self) -> ActorResult<bool> {
+        Ok(self.console)
+    }
+
+    #[GetPermissionStorage]
+    #[doc = "Returns the storage player and group permissions are kept in."]
+    pub fn permission_storage(&self) -> ActorResult<Arc<dyn PermissionStorage>> {
+        Ok(self.permissions.clone())
+    }
+
+    #[GetPacketListeners]
+    pub(crate) fn packet_listeners(&self) -> ActorResult<Arc<PacketListeners>> {
+        Ok(self.packet_listeners.clone())
+    }
+
     #[NewEntityId]
     pub fn new_entity_id(&mut self) -> ActorResult<i32> {
         self.last_entity_id += 1;
@@ -148,6 +289,32 @@ impl ServerData {
         Ok(dim)
     }
 
+    #[LocalAddresses]
+    #[doc = "The addresses the server is listening on, with the port the system picked for any address bound to port 0."]
+    pub fn local_addrs(&self) -> ActorResult<Vec<SocketAddr>> {
+        Ok(self.local_addrs.clone())
+    }
+
+    #[ConnectionCount]
+    pub fn connection_count(&self) -> ActorResult<usize> {
+        Ok(self.connections.len())
+    }
+
+    #[MaxConnections]
+    pub fn max_connections(&self) -> ActorResult<Option<usize>> {
+        Ok(self.max_connections)
+    }
+
+    #[Motd]
+    pub fn motd(&self) -> ActorResult<Text> {
+        Ok(self.motd.clone())
+    }
+
+    #[Favicon]
+    pub fn favicon(&self) -> ActorResult<Option<Vec<u8>>> {
+        Ok(self.favicon.clone())
+    }
+
     #[GetConnections]
     pub fn connections(&self) -> ActorResult<Vec<Player>> {
         Ok(self.connections.iter().map(|x| x.lower()).collect())
@@ -167,6 +334,13 @@ impl ServerData {
         Ok(vec)
     }
 
+    #[UpdateLatency]
+    #[doc = "Queues a player's new latency, which is sent to every player on the next tick."]
+    pub(crate) fn update_latency(&mut self, uuid: Uuid, latency: i32) -> ActorResult<()> {
+        self.pending_latencies.insert(uuid, latency);
+        Ok(())
+    }
+
     #[GetPlayerByUuid]
     pub fn player(&self, player: Uuid) -> ActorResult<Player> {
         for conn in &self.connections {
@@ -181,9 +355,12 @@ impl ServerData {
 }
 
 impl ServerData {
-    pub fn start(self) {
+    pub fn start(self, listeners: Vec<TcpListener>) -> std::io::Result<()> {
         log::info!("A server is starting!");
         let snd = self.as_actor();
+        let (reactor, reactor_thread) = NetworkReactor::start(snd.clone())?;
+        // Every thread that owns a socket, which must be closed before `run` returns.
+        let mut threads = vec![reactor_thread];
 
         if let Some(pack) = self.texture_pack.clone() {
             std::thread::spawn(move || {
@@ -191,9 +368,14 @@ impl ServerData {
             });
         }
 
-        SERVER_INSTANCE.set(snd.clone()).unwrap_or_else(|_| {
+        if SERVER_INSTANCE
+            .lock()
+            .unwrap()
+            .replace(snd.clone())
+            .is_some()
+        {
             log::error!("WyvernMC does not support running two servers at once. Bugs may occur.");
-        });
+        }
         let snd_clone = snd.clone();
         Runtime::spawn_task(async move {
             snd_clone
@@ -203,28 +385,53 @@ impl ServerData {
                 .unwrap();
             Ok(())
         });
-        let snd_clone = snd.clone();
-
-        Runtime::spawn_actor(
-            move || Self::networking_loop(snd_clone),
-            "ServerNetworkingThread",
-        );
+        if let Some(rcon) = self.rcon.clone() {
+            threads.extend(start_rcon(rcon, snd.clone()));
+        }
+        if let Some(addr) = self.query {
+            threads.extend(start_query(addr, snd.clone()));
+        }
+        if self.console {
+            start_console();
+        }
+        for listener in listeners {
+            let snd_clone = snd.clone();
+            let reactor = reactor.clone();
+            threads.push(Runtime::spawn_actor(
+                move || Self::networking_loop(snd_clone, listener, reactor),
+                "ServerNetworkingThread",
+            ));
+        }
         self.handle_loops(snd);
+
+        // These threads return once they notice that the server's actor is gone, dropping
+        // their sockets, so the addresses can be bound again as soon as `run` returns.
+        for thread in threads {
+            let _ = thread.join();
+        }
+        Ok(())
     }
 
     pub fn handle_loops(mut self, server: Server) {
         loop {
-            self.connections
-                .retain_mut(|connection| connection._signal.try_recv().is_err());
+            self.connections.retain(|connection| connection.is_alive());
 
+            if let Ok(message) = self.receiver.recv_timeout(self.ticks.until_next_tick()) {
+                self.handle_message(message);
+            }
             self.handle_messages();
-            let dur = Instant::now().duration_since(self.last_tick);
-            if dur > Duration::from_millis(50) {
-                self.last_tick = Instant::now();
+            if let Some(reason) = self.stop_reason.take() {
+                self.stop(server, reason);
+                return;
+            }
+            if let Some(tick) = self.ticks.begin_tick() {
+                let started = Instant::now();
 
-                let _ = server.spawn_event(ServerTickEvent {
+                let pending = ServerTickEvent {
                     server: server.clone(),
-                });
+                    tick,
+                }
+                .dispatch_tracked(self.events.clone());
 
                 for c in self.connections.iter() {
                     let mut player = c.lower();
@@ -232,31 +439,183 @@ impl ServerData {
                         Runtime::spawn_task(async move { player.update_components() });
                     }
                 }
+                self.broadcast_latencies();
+
+                // Ticks never overlap, so handlers of the next tick always see the results
+                // of this one.
+                self.handle_messages_until(pending.finished());
+                self.ticks.end_tick(started);
             }
         }
     }
 
-    pub fn networking_loop(server: Server) {
-        let listener =
-            std::net::TcpListener::bind(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 25565))
-                .unwrap();
+    /// Sends the latencies reported since the last tick to every player, all in one packet.
+    fn broadcast_latencies(&mut self) {
+        if self.pending_latencies.is_empty() {
+            return;
+        }
+        let latencies = self.pending_latencies.drain().collect::<Vec<_>>();
+        for connection in &self.connections {
+            if *connection.stage.lock().unwrap() != Stage::Play {
+                continue;
+            }
+            let player = connection.lower();
+            let latencies = latencies.clone();
+            Runtime::spawn_task(async move {
+                player.write_packet(PlayerInfoUpdateS2CPlayPacket {
+                    actions: latencies
+                        .into_iter()
+                        .map(|(uuid, latency)| {
+                            (
+                                uuid,
+                                vec![PlayerActionEntry::Latency(VarInt::from(latency))],
+                            )
+                        })
+                        .collect(),
+                })
+            });
+        }
+    }
 
-        log::info!("A server is now listening on: 127.0.0.1:25565");
+    /// Keeps handling messages sent to the server until `done` disconnects.
+    fn handle_messages_until(&mut self, done: &Receiver<()>) {
         loop {
-            let new_client = listener.accept();
-            match new_client {
-                Ok((stream, addr)) => {
-                    log::info!("Accepted new client: {:?}", addr);
-                    let stage = Arc::new(Mutex::new(Stage::Handshake));
-                    let signal = ConnectionData::connection_channel(
-                        stream,
-                        addr.ip(),
-                        server.clone(),
-                        stage,
+            let message = Selector::new()
+                .recv(&self.receiver, |message| message.ok())
+                .recv(done, |_| None)
+                .wait();
+            match message {
+                Some(message) => self.handle_message(message),
+                None => return,
+            }
+        }
+    }
+
+    fn stop(&mut self, server: Server, reason: Text) {
+        log::info!("The server is stopping...");
+
+        let pending = ServerStopEvent {
+            server: server.clone(),
+            reason: reason.clone(),
+        }
+        .dispatch_tracked(self.events.clone());
+        self.handle_messages_until(pending.finished());
+
+        for connection in &self.connections {
+            let player = connection.lower();
+            let reason = reason.clone();
+            Runtime::spawn_task(async move { player.kick(reason) });
+        }
+        let deadline = Instant::now() + KICK_TIMEOUT;
+        loop {
+            self.connections.retain(|connection| connection.is_alive());
+            if self.connections.is_empty() {
+                break;
+            }
+            // Sleeps until a connection stops or a message arrives, since disconnecting
+            // players may still need the server.
+            let message = self
+                .connections
+                .iter()
+                .fold(
+                    Selector::new().recv(&self.receiver, |message| message.ok()),
+                    |selector, connection| selector.recv(&connection._signal, |_| None),
+                )
+                .wait_deadline(deadline);
+            match message {
+                Ok(Some(message)) => self.handle_message(message),
+                Ok(None) => {}
+                Err(_) => {
+                    log::warn!(
+                        "{} connections did not close in time",
+                        self.connections.len()
                     );
-                    let _ = server.spawn_connection_internal(signal);
+                    break;
+                }
+            }
+        }
+
+        let (stopped, all_stopped) = flume::bounded::<()>(0);
+        let stopping_dimensions = self
+            .dimensions
+            .dimensions()
+            .cloned()
+            .map(|dim| {
+                let stopped = stopped.clone();
+                Runtime::run_async(async move {
+                    let result = dim.stop();
+                    drop(stopped);
+                    result
+                })
+            })
+            .collect::<Vec<_>>();
+        drop(stopped);
+        self.handle_messages_until(&all_stopped);
+        drop(stopping_dimensions);
+
+        self.worker_shutdown.take();
+        // Another server may have replaced this one as the instance in the meantime.
+        let mut instance = SERVER_INSTANCE.lock().unwrap();
+        let is_instance = instance
+            .as_ref()
+            .and_then(|instance| instance.sender.upgrade())
+            .is_some_and(|sender| sender.same_channel(&self.sender));
+        if is_instance {
+            instance.take();
+        }
+        drop(instance);
+        log::info!("The server has stopped.");
+    }
+
+    pub fn networking_loop(server: Server, listener: TcpListener, reactor: Arc<NetworkReactor>) {
+        if let Ok(addr) = listener.local_addr() {
+            log::info!("A server is now listening on: {}", addr);
+        }
+        listener.set_nonblocking(true).unwrap();
+        let mut listener = mio::net::TcpListener::from_std(listener);
+        let mut poll = Poll::new().unwrap();
+        poll.registry()
+            .register(&mut listener, Token(0), Interest::READABLE)
+            .unwrap();
+        let mut events = Events::with_capacity(16);
+
+        loop {
+            let _ = poll.poll(&mut events, Some(Duration::from_millis(500)));
+            // The server has stopped once its actor is gone.
+            if server.sender.upgrade().is_none() {
+                return;
+            }
+
+            loop {
+                match listener.accept() {
+                    Ok((stream, addr)) => {
+                        if let Ok(Some(max)) = server.max_connections() {
+                            if server.connection_count().unwrap_or(0) >= max {
+                                log::info!(
+                                    "Rejected client {:?}, the server is at its connection limit",
+                                    addr
+                                );
+                                drop(stream);
+                                continue;
+                            }
+                        }
+                        log::info!("Accepted new client: {:?}", addr);
+                        let stage = Arc::new(Mutex::new(Stage::Handshake));
+                        let signal = ConnectionData::connection_channel(
+                            stream,
+                            addr.ip(),
+                            server.clone(),
+                            reactor.clone(),
+                            stage,
+                        );
+                        let _ = server.spawn_connection_internal(signal);
+                    }
+                    Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                    Err(err) => {
+                        log::warn!("Failed to accept a client: {:?}", err);
+                        break;
+                    }
                 }
-                Err(_err) => {}
             }
         }
     }
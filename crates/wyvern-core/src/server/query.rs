@@ -0,0 +1,291 @@
+use std::{
+    collections::HashMap,
+    io::ErrorKind,
+    net::{SocketAddr, UdpSocket},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use wyvern_values::cell::Token;
+
+use crate::{
+    actors::ActorResult,
+    events::{Event, ServerStatusEvent},
+    player::SERVER_BRAND,
+    runtime::Runtime,
+};
+
+use super::{Server, ServerStatus};
+
+const MAGIC: [u8; 2] = [0xFE, 0xFD];
+const TYPE_HANDSHAKE: u8 = 9;
+const TYPE_STAT: u8 = 0;
+/// Challenge tokens are only valid for this long, like in vanilla.
+const CHALLENGE_LIFETIME: Duration = Duration::from_secs(30);
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// A request of the GameSpy4 query protocol.
+#[derive(Debug, Clone, PartialEq)]
+enum QueryRequest {
+    Handshake { session: i32 },
+    BasicStat { session: i32, challenge: i32 },
+    FullStat { session: i32, challenge: i32 },
+}
+
+impl QueryRequest {
+    fn parse(data: &[u8]) -> Option<QueryRequest> {
+        if data.len() < 7 || data[0..2] != MAGIC {
+            return None;
+        }
+        let session = i32::from_be_bytes([data[3], data[4], data[5], data[6]]) & 0x0F0F0F0F;
+        match (data[2], data.len()) {
+            (TYPE_HANDSHAKE, _) => Some(QueryRequest::Handshake { session }),
+            (TYPE_STAT, 11) => Some(QueryRequest::BasicStat {
+                session,
+                challenge: i32::from_be_bytes([data[7], data[8], data[9], data[10]]),
+            }),
+            (TYPE_STAT, 15) => Some(QueryRequest::FullStat {
+                session,
+                challenge: i32::from_be_bytes([data[7], data[8], data[9], data[10]]),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Everything a query response reports about the server.
+#[derive(Debug, Clone, PartialEq)]
+struct QueryInfo {
+    motd: String,
+    version: String,
+    plugins: String,
+    map: String,
+    online_players: i32,
+    max_players: i32,
+    host_port: u16,
+    host_ip: String,
+    players: Vec<String>,
+}
+
+impl QueryInfo {
+    /// Builds the query info from the same status the server list sees.
+    fn collect(server: &Server, remote_addr: SocketAddr) -> ActorResult<QueryInfo> {
+        let status = Token::new(ServerStatus::collect(server)?);
+        let protocol_version = status.get().protocol_version;
+        ServerStatusEvent {
+            server: server.clone(),
+            remote_addr: remote_addr.ip(),
+            protocol_version,
+            status: status.clone(),
+        }
+        .dispatch_tracked(server.event_bus()?)
+        .wait();
+        let status = status.get();
+
+        let host = server.local_addrs()?.first().copied();
+        Ok(QueryInfo {
+            motd: status.motd.to_plain(),
+            version: status.version_name,
+            plugins: SERVER_BRAND.to_string(),
+            map: server.default_dimension()?.to_string(),
+            online_players: status.online_players,
+            max_players: status.max_players,
+            host_port: host.map(|addr| addr.port()).unwrap_or(25565),
+            host_ip: host
+                .map(|addr| addr.ip().to_string())
+                .unwrap_or_else(|| "127.0.0.1".to_string()),
+            players: server
+                .players()?
+                .iter()
+                .filter_map(|player| player.username().ok())
+                .collect(),
+        })
+    }
+
+    fn basic_stat(&self, session: i32) -> Vec<u8> {
+        let mut data = vec![TYPE_STAT];
+        data.extend_from_slice(&session.to_be_bytes());
+        for value in [
+            &self.motd,
+            "SMP",
+            &self.map,
+            &self.online_players.to_string(),
+            &self.max_players.to_string(),
+        ] {
+            write_string(&mut data, value);
+        }
+        data.extend_from_slice(&self.host_port.to_le_bytes());
+        write_string(&mut data, &self.host_ip);
+        data
+    }
+
+    fn full_stat(&self, session: i32) -> Vec<u8> {
+        let mut data = vec![TYPE_STAT];
+        data.extend_from_slice(&session.to_be_bytes());
+        // This padding is always the same, clients only check its length.
+        data.extend_from_slice(b"splitnum\x00\x80\x00");
+        for (key, value) in [
+            ("hostname", self.motd.clone()),
+            ("gametype", "SMP".to_string()),
+            ("game_id", "MINECRAFT".to_string()),
+            ("version", self.version.clone()),
+            ("plugins", self.plugins.clone()),
+            ("map", self.map.clone()),
+            ("numplayers", self.online_players.to_string()),
+            ("maxplayers", self.max_players.to_string()),
+            ("hostport", self.host_port.to_string()),
+            ("hostip", self.host_ip.clone()),
+        ] {
+            write_string(&mut data, key);
+            write_string(&mut data, &value);
+        }
+        data.push(0);
+
+        data.extend_from_slice(b"\x01player_\x00\x00");
+        for player in &self.players {
+            write_string(&mut data, player);
+        }
+        data.push(0);
+        data
+    }
+}
+
+fn write_string(data: &mut Vec<u8>, value: &str) {
+    // Strings are null-terminated, so they may not contain nulls themselves.
+    data.extend(value.bytes().filter(|byte| *byte != 0));
+    data.push(0);
+}
+
+/// Starts answering query requests in the background. Returns the thread that owns the
+/// socket, which stops once the server has stopped.
+pub(crate) fn start_query(addr: SocketAddr, server: Server) -> Option<JoinHandle<()>> {
+    let socket = match UdpSocket::bind(addr) {
+        Ok(socket) => socket,
+        Err(err) => {
+            log::error!("Failed to bind the query listener to {:?}: {:?}", addr, err);
+            return None;
+        }
+    };
+    log::info!("Query is now listening on: {}", addr);
+    Some(Runtime::spawn_actor(
+        move || query_loop(server, socket),
+        "QueryNetworkingThread",
+    ))
+}
+
+fn query_loop(server: Server, socket: UdpSocket) {
+    let _ = socket.set_read_timeout(Some(POLL_INTERVAL));
+    let mut challenges: HashMap<SocketAddr, (i32, Instant)> = HashMap::new();
+    let mut buf = [0; 32];
+
+    loop {
+        // The server has stopped once its actor is gone.
+        if server.sender.upgrade().is_none() {
+            return;
+        }
+        challenges.retain(|_, (_, created)| created.elapsed() < CHALLENGE_LIFETIME);
+
+        let (len, addr) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => continue,
+            Err(e) => {
+                log::debug!("Failed to receive a query request: {:?}", e);
+                continue;
+            }
+        };
+        let Some(request) = QueryRequest::parse(&buf[..len]) else {
+            continue;
+        };
+
+        let response = match request {
+            QueryRequest::Handshake { session } => {
+                let challenge = rand::random::<i32>() & 0x00FFFFFF;
+                challenges.insert(addr, (challenge, Instant::now()));
+                let mut data = vec![TYPE_HANDSHAKE];
+                data.extend_from_slice(&session.to_be_bytes());
+                write_string(&mut data, &challenge.to_string());
+                data
+            }
+            QueryRequest::BasicStat { session, challenge }
+            | QueryRequest::FullStat { session, challenge } => {
+                // Answering without a valid challenge would let the query be used to amplify
+                // traffic towards spoofed addresses.
+                if challenges.get(&addr).map(|(token, _)| *token) != Some(challenge) {
+                    continue;
+                }
+                let Ok(info) = QueryInfo::collect(&server, addr) else {
+                    continue;
+                };
+                if matches!(request, QueryRequest::FullStat { .. }) {
+                    info.full_stat(session)
+                } else {
+                    info.basic_stat(session)
+                }
+            }
+        };
+        let _ = socket.send_to(&response, addr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{QueryInfo, QueryRequest};
+
+    fn info() -> QueryInfo {
+        QueryInfo {
+            motd: "A Wyvern Server".to_string(),
+            version: "1.21.4".to_string(),
+            plugins: "WyvernMC".to_string(),
+            map: "minecraft:overworld".to_string(),
+            online_players: 2,
+            max_players: 20,
+            host_port: 25565,
+            host_ip: "127.0.0.1".to_string(),
+            players: vec!["Alice".to_string(), "Bob".to_string()],
+        }
+    }
+
+    #[test]
+    fn requests_are_parsed() {
+        assert_eq!(
+            QueryRequest::parse(&[0xFE, 0xFD, 9, 0xFF, 0xFF, 0xFF, 0xFF]),
+            Some(QueryRequest::Handshake {
+                session: 0x0F0F0F0F
+            })
+        );
+        assert_eq!(
+            QueryRequest::parse(&[0xFE, 0xFD, 0, 0, 0, 0, 1, 0, 0, 0, 5]),
+            Some(QueryRequest::BasicStat {
+                session: 1,
+                challenge: 5
+            })
+        );
+        assert_eq!(
+            QueryRequest::parse(&[0xFE, 0xFD, 0, 0, 0, 0, 1, 0, 0, 0, 5, 0, 0, 0, 0]),
+            Some(QueryRequest::FullStat {
+                session: 1,
+                challenge: 5
+            })
+        );
+        assert_eq!(QueryRequest::parse(&[0xFE, 0xFD, 0, 0, 0, 0, 1]), None);
+        assert_eq!(QueryRequest::parse(&[0xFE, 0xFC, 9, 0, 0, 0, 1]), None);
+    }
+
+    #[test]
+    fn basic_stat_layout() {
+        let data = info().basic_stat(1);
+        let mut expected = vec![0, 0, 0, 0, 1];
+        expected.extend_from_slice(b"A Wyvern Server\x00SMP\x00minecraft:overworld\x002\x0020\x00");
+        expected.extend_from_slice(&25565u16.to_le_bytes());
+        expected.extend_from_slice(b"127.0.0.1\0");
+        assert_eq!(data, expected);
+    }
+
+    #[test]
+    fn full_stat_lists_players() {
+        let data = info().full_stat(1);
+        assert!(data.ends_with(b"\x01player_\0\0Alice\0Bob\0\0"));
+        let text = String::from_utf8_lossy(&data);
+        assert!(text.contains("numplayers\x002\x00maxplayers\x0020\x00"));
+    }
+}
@@ -0,0 +1,308 @@
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::Arc,
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    commands::{CommandSender, ConsoleSender, ConsoleSource},
+    events::{CommandEvent, Event},
+    runtime::Runtime,
+};
+
+use super::Server;
+
+const TYPE_AUTH: i32 = 3;
+const TYPE_AUTH_RESPONSE: i32 = 2;
+const TYPE_EXEC_COMMAND: i32 = 2;
+const TYPE_RESPONSE_VALUE: i32 = 0;
+
+/// The largest request vanilla servers accept, including the id and type.
+const MAX_REQUEST_SIZE: usize = 1460;
+/// Longer responses are split into multiple packets.
+const MAX_RESPONSE_BODY: usize = 4096;
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(250);
+/// How many RCON clients may be connected at once. Further connections are closed right away.
+const MAX_CLIENTS: usize = 8;
+/// How often a client waiting for its next request checks whether the server has stopped.
+const CLIENT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How long a client may go without sending a request, or take to send the rest of one,
+/// before it is disconnected.
+const CLIENT_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone)]
+pub(crate) struct RconConfig {
+    pub(crate) addr: SocketAddr,
+    pub(crate) password: String,
+}
+
+/// A packet of the Source RCON protocol.
+#[derive(Debug, Clone, PartialEq)]
+struct RconPacket {
+    id: i32,
+    kind: i32,
+    body: String,
+}
+
+impl RconPacket {
+    fn read(stream: &mut impl Read) -> std::io::Result<RconPacket> {
+        let mut length = [0; 4];
+        stream.read_exact(&mut length)?;
+        let length = i32::from_le_bytes(length);
+        if !(10..=MAX_REQUEST_SIZE as i32).contains(&length) {
+            return Err(ErrorKind::InvalidData.into());
+        }
+
+        let mut data = vec![0; length as usize];
+        stream.read_exact(&mut data)?;
+        if data[data.len() - 2..] != [0, 0] {
+            return Err(ErrorKind::InvalidData.into());
+        }
+        Ok(RconPacket {
+            id: i32::from_le_bytes([data[0], data[1], data[2], data[3]]),
+            kind: i32::from_le_bytes([data[4], data[5], data[6], data[7]]),
+            body: String::from_utf8_lossy(&data[8..data.len() - 2]).into_owned(),
+        })
+    }
+
+    fn write(&self, stream: &mut impl Write) -> std::io::Result<()> {
+        let body = self.body.as_bytes();
+        let mut data = Vec::with_capacity(body.len() + 14);
+        data.extend_from_slice(&(body.len() as i32 + 10).to_le_bytes());
+        data.extend_from_slice(&self.id.to_le_bytes());
+        data.extend_from_slice(&self.kind.to_le_bytes());
+        data.extend_from_slice(body);
+        data.extend_from_slice(&[0, 0]);
+        stream.write_all(&data)
+    }
+}
+
+/// Starts accepting RCON connections in the background. Returns the thread that owns the
+/// listener, which stops once the server has stopped.
+pub(crate) fn start_rcon(config: RconConfig, server: Server) -> Option<JoinHandle<()>> {
+    let listener = match TcpListener::bind(config.addr) {
+        Ok(listener) => listener,
+        Err(err) => {
+            log::error!("Failed to bind RCON to {:?}: {:?}", config.addr, err);
+            return None;
+        }
+    };
+    log::info!("RCON is now listening on: {}", config.addr);
+    let password = Arc::new(config.password);
+    Some(Runtime::spawn_actor(
+        move || rcon_loop(server, listener, password),
+        "RconNetworkingThread",
+    ))
+}
+
+fn rcon_loop(server: Server, listener: TcpListener, password: Arc<String>) {
+    listener.set_nonblocking(true).unwrap();
+    let mut clients: Vec<JoinHandle<()>> = Vec::new();
+    loop {
+        // The server has stopped once its actor is gone.
+        if server.sender.upgrade().is_none() {
+            // Clients notice the server has stopped within a poll interval.
+            for client in clients {
+                let _ = client.join();
+            }
+            return;
+        }
+
+        match listener.accept() {
+            Ok((stream, addr)) => {
+                clients.retain(|client| !client.is_finished());
+                if clients.len() >= MAX_CLIENTS {
+                    log::warn!(
+                        "Closing RCON connection from {:?}, {} clients are already connected",
+                        addr,
+                        MAX_CLIENTS
+                    );
+                    continue;
+                }
+                let server = server.clone();
+                let password = password.clone();
+                clients.push(Runtime::spawn_actor(
+                    move || {
+                        if let Err(err) = handle_client(stream, addr, &server, &password) {
+                            log::debug!("RCON client {:?} disconnected: {:?}", addr, err);
+                        }
+                    },
+                    "RconClientThread",
+                ));
+            }
+            // RCON sees little traffic, so polling the listener is cheap enough.
+            Err(e) if e.kind() == ErrorKind::WouldBlock => std::thread::sleep(ACCEPT_INTERVAL),
+            Err(e) => log::error!("Failed to accept an RCON connection: {:?}", e),
+        }
+    }
+}
+
+fn handle_client(
+    mut stream: TcpStream,
+    addr: SocketAddr,
+    server: &Server,
+    password: &str,
+) -> std::io::Result<()> {
+    stream.set_nonblocking(false)?;
+    let mut authenticated = false;
+    loop {
+        if !wait_for_request(&stream, server)? {
+            return Ok(());
+        }
+        stream.set_read_timeout(Some(CLIENT_IDLE_TIMEOUT))?;
+        let packet = match RconPacket::read(&mut stream) {
+            Ok(packet) => packet,
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        };
+
+        match packet.kind {
+            TYPE_AUTH => {
+                authenticated = !password.is_empty() && packet.body == password;
+                if authenticated {
+                    log::info!("RCON client {:?} logged in", addr);
+                } else {
+                    log::warn!("RCON client {:?} used a wrong password", addr);
+                }
+                RconPacket {
+                    id: if authenticated { packet.id } else { -1 },
+                    kind: TYPE_AUTH_RESPONSE,
+                    body: String::new(),
+                }
+                .write(&mut stream)?;
+            }
+            TYPE_EXEC_COMMAND if authenticated => {
+                log::info!("RCON client {:?} issued command: {}", addr, packet.body);
+                let output = run_command(server, addr, packet.body);
+                for body in split_response(&output) {
+                    RconPacket {
+                        id: packet.id,
+                        kind: TYPE_RESPONSE_VALUE,
+                        body,
+                    }
+                    .write(&mut stream)?;
+                }
+            }
+            TYPE_EXEC_COMMAND => {
+                RconPacket {
+                    id: -1,
+                    kind: TYPE_AUTH_RESPONSE,
+                    body: String::new(),
+                }
+                .write(&mut stream)?;
+            }
+            kind => {
+                RconPacket {
+                    id: packet.id,
+                    kind: TYPE_RESPONSE_VALUE,
+                    body: format!("Unknown request {:x}", kind),
+                }
+                .write(&mut stream)?;
+            }
+        }
+    }
+}
+
+/// Waits until the client starts sending a request. Returns `false` if the client closed
+/// the connection, stayed idle for too long, or the server stopped in the meantime.
+fn wait_for_request(stream: &TcpStream, server: &Server) -> std::io::Result<bool> {
+    stream.set_read_timeout(Some(CLIENT_POLL_INTERVAL))?;
+    let idle_since = Instant::now();
+    loop {
+        if server.sender.upgrade().is_none() || idle_since.elapsed() >= CLIENT_IDLE_TIMEOUT {
+            return Ok(false);
+        }
+        match stream.peek(&mut [0]) {
+            Ok(0) => return Ok(false),
+            Ok(_) => return Ok(true),
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Runs a command, or fires a `CommandEvent` if it is not registered, and waits for its handlers to produce the output.
+fn run_command(server: &Server, addr: SocketAddr, command: String) -> String {
+    let Ok(bus) = server.event_bus() else {
+        return String::new();
+    };
+    let sender = ConsoleSender::new(ConsoleSource::Rcon(addr));
+    let commands = server.commands().unwrap_or_default();
+    if commands.contains(&command) {
+        let _ = futures::executor::block_on(commands.dispatch(
+            server.clone(),
+            CommandSender::Console(sender.clone()),
+            command,
+        ));
+    } else {
+        CommandEvent {
+            server: server.clone(),
+            sender: CommandSender::Console(sender.clone()),
+            command,
+        }
+        .dispatch_tracked(bus)
+        .wait();
+    }
+
+    sender
+        .output()
+        .iter()
+        .map(|line| line.to_plain())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Splits a response into bodies that fit in a single packet. There is always at least one
+/// body, so clients get an answer even if the command had no output.
+fn split_response(output: &str) -> Vec<String> {
+    let mut bodies = Vec::new();
+    let mut rest = output;
+    while rest.len() > MAX_RESPONSE_BODY {
+        let mut end = MAX_RESPONSE_BODY;
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        bodies.push(rest[..end].to_string());
+        rest = &rest[end..];
+    }
+    bodies.push(rest.to_string());
+    bodies
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MAX_RESPONSE_BODY, RconPacket, split_response};
+
+    #[test]
+    fn packet_round_trip() {
+        let packet = RconPacket {
+            id: 7,
+            kind: 2,
+            body: "say hi".to_string(),
+        };
+        let mut data = Vec::new();
+        packet.write(&mut data).unwrap();
+        assert_eq!(&data[0..4], &16i32.to_le_bytes());
+        assert_eq!(RconPacket::read(&mut data.as_slice()).unwrap(), packet);
+    }
+
+    #[test]
+    fn oversized_packets_are_rejected() {
+        let mut data = 5000i32.to_le_bytes().to_vec();
+        data.extend_from_slice(&[0; 5000]);
+        assert!(RconPacket::read(&mut data.as_slice()).is_err());
+    }
+
+    #[test]
+    fn long_responses_are_split() {
+        assert_eq!(split_response(""), [""]);
+        let output = "é".repeat(MAX_RESPONSE_BODY);
+        let bodies = split_response(&output);
+        assert_eq!(bodies.len(), 2);
+        assert!(bodies.iter().all(|body| body.len() <= MAX_RESPONSE_BODY));
+        assert_eq!(bodies.concat(), output);
+    }
+}
@@ -0,0 +1,97 @@
+use std::{
+    collections::HashMap,
+    io::ErrorKind,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use flume::{Receiver, Sender};
+use mio::{Events, Interest, Poll, Registry, Token, net::TcpStream};
+
+use crate::runtime::Runtime;
+
+use super::Server;
+
+/// Watches every connection's socket with a single OS-level poller (epoll, kqueue, ...) and
+/// wakes the connection's thread whenever its socket becomes readable or writable, so idle
+/// connections do not use any CPU time.
+///
+/// Connections keep a thread each instead of sharing a pool of workers. Connections and
+/// event handlers call other players' actors and wait for the answer, which would deadlock
+/// whenever both players were driven by the same worker. A sleeping connection thread only
+/// costs its stack.
+pub(crate) struct NetworkReactor {
+    registry: Registry,
+    wakers: Mutex<HashMap<Token, Sender<()>>>,
+    next_token: AtomicUsize,
+}
+
+impl NetworkReactor {
+    pub(crate) fn start(server: Server) -> std::io::Result<(Arc<NetworkReactor>, JoinHandle<()>)> {
+        let poll = Poll::new()?;
+        let reactor = Arc::new(NetworkReactor {
+            registry: poll.registry().try_clone()?,
+            wakers: Mutex::new(HashMap::new()),
+            next_token: AtomicUsize::new(0),
+        });
+
+        let reactor_clone = reactor.clone();
+        let thread = Runtime::spawn_actor(
+            move || reactor_clone.run(poll, server),
+            "NetworkReactorThread",
+        );
+        Ok((reactor, thread))
+    }
+
+    fn run(&self, mut poll: Poll, server: Server) {
+        let mut events = Events::with_capacity(1024);
+        loop {
+            match poll.poll(&mut events, Some(Duration::from_millis(500))) {
+                Ok(()) => {}
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => {
+                    log::error!("The network reactor failed to poll: {:?}", err);
+                    return;
+                }
+            }
+
+            let wakers = self.wakers.lock().unwrap();
+            for event in events.iter() {
+                if let Some(waker) = wakers.get(&event.token()) {
+                    // A full channel means the connection has not woken up yet, so this event
+                    // would be redundant.
+                    let _ = waker.try_send(());
+                }
+            }
+            drop(wakers);
+
+            // The server has stopped once its actor is gone.
+            if server.sender.upgrade().is_none() {
+                return;
+            }
+        }
+    }
+
+    /// Registers a socket with the reactor. The returned receiver gets a message every time
+    /// the socket has new readiness events.
+    pub(crate) fn register(
+        &self,
+        stream: &mut TcpStream,
+    ) -> std::io::Result<(Token, Receiver<()>)> {
+        let token = Token(self.next_token.fetch_add(1, Ordering::Relaxed));
+        let (waker, wake) = flume::bounded(1);
+        self.wakers.lock().unwrap().insert(token, waker);
+        self.registry
+            .register(stream, token, Interest::READABLE | Interest::WRITABLE)?;
+        Ok((token, wake))
+    }
+
+    pub(crate) fn deregister(&self, stream: &mut TcpStream, token: Token) {
+        let _ = self.registry.deregister(stream);
+        self.wakers.lock().unwrap().remove(&token);
+    }
+}
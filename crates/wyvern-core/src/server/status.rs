@@ -0,0 +1,154 @@
+use base64::{Engine, engine::general_purpose::STANDARD};
+use voxidian_protocol::{
+    MINECRAFT_VERSION, PROTOCOL_VERSION,
+    packet::s2c::status::{
+        StatusResponse, StatusResponsePlayer, StatusResponsePlayers, StatusResponseVersion,
+    },
+    value::{Text as PtcText, Uuid},
+};
+use wyvern_datatypes::text::Text;
+
+use crate::actors::ActorResult;
+
+use super::Server;
+
+/// The information shown to clients in the server list.
+#[derive(Debug, Clone)]
+pub struct ServerStatus {
+    pub version_name: String,
+    pub protocol_version: i32,
+    pub motd: Text,
+    /// The raw bytes of a 64x64 PNG image.
+    pub favicon: Option<Vec<u8>>,
+    pub online_players: i32,
+    pub max_players: i32,
+    /// The names and UUIDs of the players shown when hovering over the player count.
+    pub sample: Vec<(String, Uuid)>,
+}
+
+impl ServerStatus {
+    /// Builds the default status of a server from its current state.
+    /// This must not be called from the server's own thread.
+    pub fn collect(server: &Server) -> ActorResult<ServerStatus> {
+        let players = server.players()?;
+        let sample = players
+            .iter()
+            .take(12)
+            .filter_map(|player| Some((player.username().ok()?, player.uuid().ok()?)))
+            .collect();
+
+        Ok(ServerStatus {
+            version_name: MINECRAFT_VERSION.to_string(),
+            protocol_version: PROTOCOL_VERSION as i32,
+            motd: server.motd()?,
+            favicon: server.favicon()?,
+            online_players: players.len() as i32,
+            max_players: server.max_connections()?.unwrap_or(100) as i32,
+            sample,
+        })
+    }
+
+    pub(crate) fn into_response(self) -> StatusResponse {
+        StatusResponse {
+            version: StatusResponseVersion {
+                name: self.version_name,
+                protocol: self.protocol_version as _,
+            },
+            players: Some(StatusResponsePlayers {
+                online: self.online_players as _,
+                max: self.max_players as _,
+                sample: self
+                    .sample
+                    .into_iter()
+                    .map(|(name, id)| StatusResponsePlayer { name, id })
+                    .collect(),
+            }),
+            desc: PtcText::from(self.motd),
+            favicon_png_b64: self
+                .favicon
+                .map(|png| format!("data:image/png;base64,{}", STANDARD.encode(png)))
+                .unwrap_or_default(),
+            enforce_chat_reports: false,
+            prevent_chat_reports: true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        sync::Arc,
+    };
+
+    use voxidian_protocol::{
+        MINECRAFT_VERSION, PROTOCOL_VERSION,
+        packet::{PacketBuf, PacketEncode},
+        value::Uuid,
+    };
+    use wyvern_datatypes::text::Text;
+    use wyvern_values::cell::Token;
+
+    use super::{Server, ServerStatus};
+    use crate::events::{Event, EventBus, EventPriority, ServerStatusEvent, run_sequential};
+
+    #[test]
+    fn event_overrides_are_encoded() {
+        let mut bus = EventBus::default();
+        ServerStatusEvent::add_handler(
+            &mut bus,
+            EventPriority::Normal,
+            |event: Arc<ServerStatusEvent>| async move {
+                event.status.update(|status| {
+                    status.motd = Text::literal("Overridden MOTD");
+                    status.max_players = 5;
+                    status.sample = vec![("Notch".to_string(), Uuid::from_u128(0x1234))];
+                });
+                Ok(())
+            },
+        );
+
+        let status = Token::new(ServerStatus {
+            version_name: MINECRAFT_VERSION.to_string(),
+            protocol_version: PROTOCOL_VERSION as i32,
+            motd: Text::literal("Default MOTD"),
+            favicon: None,
+            online_players: 1,
+            max_players: 100,
+            sample: vec![("jeb_".to_string(), Uuid::from_u128(0x5678))],
+        });
+        let (sender, _receiver) = flume::unbounded();
+        let event = Arc::new(ServerStatusEvent {
+            server: Server {
+                sender: sender.downgrade(),
+            },
+            remote_addr: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            protocol_version: PROTOCOL_VERSION as i32,
+            status: status.clone(),
+        });
+        futures::executor::block_on(run_sequential(bus.on_status.clone(), event)).unwrap();
+
+        let mut buf = PacketBuf::new();
+        status
+            .get()
+            .into_response()
+            .to_packet()
+            .encode(&mut buf)
+            .unwrap();
+        let bytes = buf.iter().collect::<Vec<u8>>();
+        // The packet is the JSON response prefixed with its length.
+        let start = bytes.iter().position(|byte| *byte == b'{').unwrap();
+        let json = json::parse(&String::from_utf8(bytes[start..].to_vec()).unwrap()).unwrap();
+
+        assert!(json["description"].dump().contains("Overridden MOTD"));
+        assert_eq!(json["players"]["max"].as_i32(), Some(5));
+        assert_eq!(json["players"]["online"].as_i32(), Some(1));
+        assert_eq!(json["players"]["sample"].len(), 1);
+        assert_eq!(json["players"]["sample"][0]["name"].as_str(), Some("Notch"));
+        assert_eq!(
+            json["players"]["sample"][0]["id"].as_str(),
+            Some(Uuid::from_u128(0x1234).to_string().as_str())
+        );
+        assert_eq!(json["version"]["name"].as_str(), Some(MINECRAFT_VERSION));
+    }
+}
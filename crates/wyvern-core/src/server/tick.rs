@@ -0,0 +1,124 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+pub(crate) const TICK_DURATION: Duration = Duration::from_millis(50);
+pub(crate) const TICKS_PER_SECOND: f64 = 20.0;
+
+/// How far a loop may fall behind before it skips ticks instead of running them back to back.
+const MAX_CATCH_UP_TICKS: u32 = 10;
+/// How many ticks TPS and MSPT are averaged over.
+const SAMPLE_SIZE: usize = 100;
+
+/// Schedules ticks at a fixed rate of 20 per second and measures how well that rate is kept.
+pub(crate) struct TickScheduler {
+    next_tick: Instant,
+    current_tick: u64,
+    tick_starts: VecDeque<Instant>,
+    tick_durations: VecDeque<Duration>,
+}
+
+impl TickScheduler {
+    pub(crate) fn new() -> TickScheduler {
+        TickScheduler {
+            next_tick: Instant::now(),
+            current_tick: 0,
+            tick_starts: VecDeque::with_capacity(SAMPLE_SIZE),
+            tick_durations: VecDeque::with_capacity(SAMPLE_SIZE),
+        }
+    }
+
+    /// The time left until the next tick is due.
+    pub(crate) fn until_next_tick(&self) -> Duration {
+        self.next_tick.saturating_duration_since(Instant::now())
+    }
+
+    /// Starts a new tick if one is due, returning its number.
+    /// A loop that is behind gets its missed ticks back to back, unless it is so far behind
+    /// that catching up would take too long, in which case the missed ticks are skipped.
+    pub(crate) fn begin_tick(&mut self) -> Option<u64> {
+        let now = Instant::now();
+        if now < self.next_tick {
+            return None;
+        }
+
+        let behind = now - self.next_tick;
+        if behind > TICK_DURATION * MAX_CATCH_UP_TICKS {
+            let skipped = behind.as_millis() / TICK_DURATION.as_millis();
+            log::warn!(
+                "Can't keep up! Running {}ms behind, skipping {} ticks",
+                behind.as_millis(),
+                skipped
+            );
+            self.next_tick = now;
+        }
+        self.next_tick += TICK_DURATION;
+        self.current_tick += 1;
+
+        if self.tick_starts.len() == SAMPLE_SIZE {
+            self.tick_starts.pop_front();
+        }
+        self.tick_starts.push_back(now);
+        Some(self.current_tick)
+    }
+
+    /// Records how long the tick started at `started` took to run.
+    pub(crate) fn end_tick(&mut self, started: Instant) {
+        if self.tick_durations.len() == SAMPLE_SIZE {
+            self.tick_durations.pop_front();
+        }
+        self.tick_durations.push_back(started.elapsed());
+    }
+
+    pub(crate) fn current_tick(&self) -> u64 {
+        self.current_tick
+    }
+
+    /// The average ticks per second over the last few seconds, at most 20.
+    pub(crate) fn tps(&self) -> f64 {
+        let (Some(first), Some(last)) = (self.tick_starts.front(), self.tick_starts.back()) else {
+            return TICKS_PER_SECOND;
+        };
+        let elapsed = last.duration_since(*first).as_secs_f64();
+        if elapsed == 0.0 {
+            return TICKS_PER_SECOND;
+        }
+        ((self.tick_starts.len() - 1) as f64 / elapsed).min(TICKS_PER_SECOND)
+    }
+
+    /// The average time in milliseconds a tick took to run over the last few seconds.
+    pub(crate) fn mspt(&self) -> f64 {
+        if self.tick_durations.is_empty() {
+            return 0.0;
+        }
+        let total = self.tick_durations.iter().sum::<Duration>();
+        total.as_secs_f64() * 1000.0 / self.tick_durations.len() as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::{MAX_CATCH_UP_TICKS, TICK_DURATION, TickScheduler};
+
+    #[test]
+    fn catches_up_missed_ticks() {
+        let mut scheduler = TickScheduler::new();
+        scheduler.next_tick = Instant::now() - TICK_DURATION * 2;
+        assert_eq!(scheduler.begin_tick(), Some(1));
+        assert_eq!(scheduler.begin_tick(), Some(2));
+        assert_eq!(scheduler.begin_tick(), Some(3));
+        assert_eq!(scheduler.begin_tick(), None);
+    }
+
+    #[test]
+    fn skips_ticks_when_far_behind() {
+        let mut scheduler = TickScheduler::new();
+        scheduler.next_tick = Instant::now() - TICK_DURATION * (MAX_CATCH_UP_TICKS * 2);
+        assert_eq!(scheduler.begin_tick(), Some(1));
+        assert_eq!(scheduler.begin_tick(), None);
+        assert!(scheduler.until_next_tick() > TICK_DURATION / 2);
+    }
+}
@@ -0,0 +1,107 @@
+//! The protocol's VarInt and string encodings, for data the protocol library does not
+//! decode itself, like plugin message payloads and compression framing.
+
+/// The most bytes a VarInt may take up.
+pub(crate) const MAX_VARINT_LEN: usize = 5;
+
+pub(crate) fn write_varint(buf: &mut Vec<u8>, value: i32) {
+    let mut value = value as u32;
+    loop {
+        if value & !0x7F == 0 {
+            buf.push(value as u8);
+            return;
+        }
+        buf.push((value as u8 & 0x7F) | 0x80);
+        value >>= 7;
+    }
+}
+
+/// Reads a VarInt from the start of `bytes`, returning its value and how many bytes it took
+/// up. Returns `None` if the bytes end before the VarInt does, or if it is too long.
+pub(crate) fn read_varint(bytes: impl IntoIterator<Item = u8>) -> Option<(i32, usize)> {
+    let mut value = 0u32;
+    for (i, byte) in bytes.into_iter().take(MAX_VARINT_LEN).enumerate() {
+        value |= ((byte & 0x7F) as u32) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Some((value as i32, i + 1));
+        }
+    }
+    None
+}
+
+/// Writes a string prefixed by its length in bytes.
+pub(crate) fn write_string(buf: &mut Vec<u8>, value: &str) {
+    write_varint(buf, value.len() as i32);
+    buf.extend_from_slice(value.as_bytes());
+}
+
+/// Reads protocol values from the front of a byte slice.
+pub(crate) struct PayloadReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> PayloadReader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> PayloadReader<'a> {
+        PayloadReader { data }
+    }
+
+    pub(crate) fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.data.len() < len {
+            return None;
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Some(bytes)
+    }
+
+    pub(crate) fn varint(&mut self) -> Option<i32> {
+        let (value, len) = read_varint(self.data.iter().copied())?;
+        self.data = &self.data[len..];
+        Some(value)
+    }
+
+    pub(crate) fn string(&mut self) -> Option<String> {
+        let len = usize::try_from(self.varint()?).ok()?;
+        String::from_utf8(self.bytes(len)?.to_vec()).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PayloadReader, read_varint, write_string, write_varint};
+
+    #[test]
+    fn varints() {
+        for (value, bytes) in [
+            (0, &[0x00][..]),
+            (127, &[0x7F]),
+            (300, &[0xAC, 0x02]),
+            (2097151, &[0xFF, 0xFF, 0x7F]),
+            (-1, &[0xFF, 0xFF, 0xFF, 0xFF, 0x0F]),
+        ] {
+            let mut buf = Vec::new();
+            write_varint(&mut buf, value);
+            assert_eq!(buf, bytes);
+            assert_eq!(read_varint(buf), Some((value, bytes.len())));
+        }
+        assert_eq!(read_varint([0xFF, 0xFF]), None);
+        assert_eq!(read_varint([0xFF; 6]), None);
+    }
+
+    #[test]
+    fn strings() {
+        let mut buf = Vec::new();
+        write_string(&mut buf, "vanilla");
+        assert_eq!(buf, b"\x07vanilla");
+
+        let long = "a".repeat(300);
+        write_string(&mut buf, &long);
+        let mut reader = PayloadReader::new(&buf);
+        assert_eq!(reader.string().as_deref(), Some("vanilla"));
+        assert_eq!(reader.string(), Some(long));
+        assert_eq!(reader.bytes(1), None);
+
+        assert_eq!(PayloadReader::new(b"\x07van").string(), None);
+        assert_eq!(PayloadReader::new(b"").string(), None);
+    }
+}
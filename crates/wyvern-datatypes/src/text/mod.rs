@@ -49,6 +49,40 @@ impl Text {
         self
     }
 
+    /// Returns the text's content without any formatting.
+    pub fn to_plain(&self) -> String {
+        match &self.content {
+            TextContent::Literal(literal) => literal.clone(),
+            TextContent::Group(texts) => texts.iter().map(Text::to_plain).collect(),
+        }
+    }
+
+    /// Renders the text with ANSI escape codes, for printing it to a terminal.
+    /// White is the default color of text, so it is left to the terminal's own color.
+    pub fn to_ansi(&self) -> String {
+        match &self.content {
+            TextContent::Literal(literal) => {
+                let mut codes = String::new();
+                let TextColor { r, g, b } = self.meta.color;
+                if (r, g, b) != (255, 255, 255) {
+                    codes.push_str(&format!("\x1b[38;2;{};{};{}m", r, g, b));
+                }
+                if self.meta.style.bold {
+                    codes.push_str("\x1b[1m");
+                }
+                if self.meta.style.italic {
+                    codes.push_str("\x1b[3m");
+                }
+                if codes.is_empty() {
+                    literal.clone()
+                } else {
+                    format!("{}{}\x1b[0m", codes, literal)
+                }
+            }
+            TextContent::Group(texts) => texts.iter().map(Text::to_ansi).collect(),
+        }
+    }
+
     pub fn and_then(self, other: Text) -> Text {
         Text {
             meta: TextMeta::default(),
@@ -104,9 +104,7 @@ pub fn message(attr: TokenStream, item: TokenStream) -> TokenStream {
                 for _ in 0..512 {
                     match self.receiver.try_recv() {
                         Ok(v) => {
-                            match v {
-                                #(#enum_arms)*
-                            }
+                            self.handle_message(v);
                         },
                         Err(flume::TryRecvError::Empty) => { return; },
                         Err(flume::TryRecvError::Disconnected) => { return; }
@@ -118,6 +116,12 @@ pub fn message(attr: TokenStream, item: TokenStream) -> TokenStream {
         impl #target_type {
             #(#assoc_fns)*
 
+            pub(crate) fn handle_message(&mut self, message: #attr_message_type) {
+                match message {
+                    #(#enum_arms)*
+                }
+            }
+
             pub fn as_actor(&self) -> #attr_actor_type {
                 #attr_actor_type { sender: self.sender.downgrade() }
             }
@@ -215,7 +219,7 @@ fn create_fn_from_variant(variant: &MessageVariant) -> TokenStream {
             let Some(sender) = self.sender.upgrade() else {
                 return Err(ActorError::ActorHasBeenDropped);
             };
-            let (tx, mut rx) = flume::bounded(1);
+            let (tx, rx) = flume::bounded(1);
             match sender.try_send(#enum_type::#enum_variant(#(#param_names,)* tx)) {
                 Ok(v) => {
                     drop(sender);
@@ -227,17 +231,10 @@ fn create_fn_from_variant(variant: &MessageVariant) -> TokenStream {
                     panic!("something went wrong :(");
                 }
             }
-            loop {
-                match rx.try_recv() {
-                    Ok(v) => return v,
-                    Err(e) if e == flume::TryRecvError::Disconnected => {
-                        return Err(ActorError::ActorHasBeenDropped);
-                    }
-                    Err(e) => {
-                        std::thread::yield_now();
-                    }
-                };
-            };
+            match rx.recv() {
+                Ok(v) => v,
+                Err(flume::RecvError::Disconnected) => Err(ActorError::ActorHasBeenDropped),
+            }
         }
     };
     r
@@ -5,10 +5,10 @@ pub fn server(_attr: TokenStream, item: TokenStream) -> TokenStream {
     let func = parse2::<ItemFn>(item).unwrap();
     let name = func.sig.ident.clone();
     quote::quote! {
-        fn main() {
+        fn main() -> std::io::Result<()> {
             #func
 
-            #name().run();
+            #name().run()
         }
     }
 }
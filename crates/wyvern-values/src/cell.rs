@@ -28,4 +28,9 @@ impl<T: Clone> Token<T> {
     pub fn get(&self) -> T {
         self.inner.lock().unwrap().clone()
     }
+
+    /// Modifies the value in place, without other handlers being able to change it in between.
+    pub fn update(&self, f: impl FnOnce(&mut T)) {
+        f(&mut self.inner.lock().unwrap());
+    }
 }
@@ -27,7 +27,7 @@ use wyvern_mc::{
 
 static COUNTER: LazyLock<Mutex<HashMap<Uuid, i32>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
 
-fn main() {
+fn main() -> std::io::Result<()> {
     env_logger::init();
 
     Server::builder()
@@ -37,7 +37,7 @@ fn main() {
         .event(on_tick)
         .event(on_right_click)
         .event(on_swap_hands)
-        .run();
+        .run()
 }
 
 async fn on_server_start(event: Arc<ServerStartEvent>) -> ActorResult<()> {
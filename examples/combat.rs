@@ -18,7 +18,7 @@ use wyvern_mc::{
     values::{DVec3, IVec3, Vec2Ext, id},
 };
 
-fn main() {
+fn main() -> std::io::Result<()> {
     env_logger::init();
 
     Server::builder()
@@ -27,7 +27,7 @@ fn main() {
         .event(on_join)
         .event(on_attack)
         .event(on_attack_player)
-        .run();
+        .run()
 }
 
 async fn on_server_start(event: Arc<ServerStartEvent>) -> ActorResult<()> {
@@ -20,7 +20,7 @@ use wyvern_mc::{
     values::{IVec3, id},
 };
 
-fn main() {
+fn main() -> std::io::Result<()> {
     env_logger::init();
 
     Server::builder()
@@ -38,7 +38,7 @@ fn main() {
                     .height(16),
             );
         })
-        .run();
+        .run()
 }
 
 async fn on_server_start(event: Arc<ServerStartEvent>) -> ActorResult<()> {
@@ -93,13 +93,11 @@ async fn on_join(event: Arc<PlayerJoinEvent>) -> ActorResult<()> {
 }
 
 async fn on_break(event: Arc<BreakBlockEvent>) -> ActorResult<()> {
-    let dim = event.player.dimension()?;
-    dim.set_block(event.position, event.old_block.clone())?;
+    event.cancel();
     Ok(())
 }
 
 async fn on_place(event: Arc<PlaceBlockEvent>) -> ActorResult<()> {
-    let dim = event.player.dimension()?;
-    dim.set_block(event.position, BlockState::new(Blocks::AIR))?;
+    event.cancel();
     Ok(())
 }
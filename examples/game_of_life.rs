@@ -24,7 +24,7 @@ const MAX_Z: usize = 50;
 
 static IS_RUNNING: AtomicBool = AtomicBool::new(false);
 
-fn main() {
+fn main() -> std::io::Result<()> {
     env_logger::init();
 
     Server::builder()
@@ -39,7 +39,7 @@ fn main() {
                 DimensionType::default().min_y(0).height(16),
             );
         })
-        .run();
+        .run()
 }
 
 async fn on_server_start(event: Arc<ServerStartEvent>) -> ActorResult<()> {
@@ -5,15 +5,15 @@ use wyvern_mc::{
     blocks::{BlockState, Blocks},
     datatypes::regval::DimensionType,
     events::{
-        DimensionCreateEvent, PlayerCommandEvent, PlayerJoinEvent, PlayerRespawnEvent,
-        ServerStartEvent, ServerTickEvent,
+        CommandEvent, DimensionCreateEvent, PlayerJoinEvent, PlayerRespawnEvent, ServerStartEvent,
+        ServerTickEvent,
     },
     player::{HealthComponent, PlayerComponents},
     server::{Server, registries::RegistryKeys},
     values::{DVec3, IVec3, Id, id},
 };
 
-fn main() {
+fn main() -> std::io::Result<()> {
     env_logger::init();
 
     Server::builder()
@@ -29,7 +29,7 @@ fn main() {
                 DimensionType::default().min_y(-64).height(128),
             );
         })
-        .run();
+        .run()
 }
 
 async fn on_server_start(event: Arc<ServerStartEvent>) -> ActorResult<()> {
@@ -61,9 +61,12 @@ async fn tick(event: Arc<ServerTickEvent>) -> ActorResult<()> {
     Ok(())
 }
 
-async fn on_command(event: Arc<PlayerCommandEvent>) -> ActorResult<()> {
+async fn on_command(event: Arc<CommandEvent>) -> ActorResult<()> {
+    let Some(player) = event.sender.player() else {
+        return Ok(());
+    };
     if event.command == "restart" {
-        event.player.set(
+        player.set(
             PlayerComponents::TELEPORT_POSITION,
             DVec3::new(0.0, 11.0, 0.0),
         )?;
@@ -3,7 +3,7 @@ use std::sync::Arc;
 use datafix::serialization::{Codec, DefaultCodec};
 use wyvern_mc::{
     actors::ActorResult,
-    blocks::{BlockState, Structure},
+    blocks::Structure,
     components::DataComponentHolder,
     datatypes::{
         nbt::{Nbt, NbtCompound, NbtOps},
@@ -88,14 +88,12 @@ async fn on_load(event: Arc<PlayerLoadEvent>) -> ActorResult<()> {
 }
 
 async fn on_break(event: Arc<BreakBlockEvent>) -> ActorResult<()> {
-    let dim = event.player.dimension()?;
-    dim.set_block(event.position, event.old_block.clone())?;
+    event.cancel();
     Ok(())
 }
 
 async fn on_place(event: Arc<PlaceBlockEvent>) -> ActorResult<()> {
-    let dim = event.player.dimension()?;
-    dim.set_block(event.position, BlockState::new(id![minecraft:air]))?;
+    event.cancel();
     Ok(())
 }
 
@@ -3,7 +3,7 @@ use std::{sync::Arc, time::Duration};
 use datafix::serialization::{Codec, DefaultCodec};
 use wyvern_mc::{
     actors::ActorResult,
-    blocks::{BlockState, Structure},
+    blocks::Structure,
     components::DataComponentHolder,
     datatypes::{
         gamemode::Gamemode,
@@ -19,7 +19,7 @@ use wyvern_mc::{
     values::{DVec3, IVec3, id},
 };
 
-fn main() {
+fn main() -> std::io::Result<()> {
     env_logger::init();
 
     Server::builder()
@@ -31,7 +31,7 @@ fn main() {
         .registries(|registries| {
             registries.add_defaults();
         })
-        .run();
+        .run()
 }
 
 async fn on_server_start(event: Arc<ServerStartEvent>) -> ActorResult<()> {
@@ -74,13 +74,11 @@ async fn on_join(event: Arc<PlayerJoinEvent>) -> ActorResult<()> {
 }
 
 async fn on_break(event: Arc<BreakBlockEvent>) -> ActorResult<()> {
-    let dim = event.player.dimension()?;
-    dim.set_block(event.position, event.old_block.clone())?;
+    event.cancel();
     Ok(())
 }
 
 async fn on_place(event: Arc<PlaceBlockEvent>) -> ActorResult<()> {
-    let dim = event.player.dimension()?;
-    dim.set_block(event.position, BlockState::new(id![minecraft:air]))?;
+    event.cancel();
     Ok(())
 }
@@ -13,7 +13,7 @@ use wyvern_mc::{
     values::{IVec3, id},
 };
 
-fn main() {
+fn main() -> std::io::Result<()> {
     env_logger::init();
 
     Server::builder()
@@ -26,7 +26,7 @@ fn main() {
                 DimensionType::default().height(64).min_y(0),
             );
         })
-        .run();
+        .run()
 }
 
 async fn on_server_start(event: Arc<ServerStartEvent>) -> ActorResult<()> {